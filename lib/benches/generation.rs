@@ -0,0 +1,55 @@
+/*
+    Pretty Der6y - A third-party running data upload client.
+    Copyright (C) 2024  Fay Ash
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published
+    by the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU Affero General Public License for more details.
+
+    You should have received a copy of the GNU Affero General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use lib::{route, Account};
+
+const ROUTE: &str = include_str!("../../assets/map.geojson");
+const MILEAGES: [f64; 3] = [5., 10., 20.];
+
+fn parsing(c: &mut Criterion) {
+    c.bench_function("normalize", |b| {
+        b.iter(|| route::normalize(black_box(ROUTE)).unwrap())
+    });
+}
+
+fn generation(c: &mut Criterion) {
+    let route = route::normalize(ROUTE).unwrap();
+    let mut group = c.benchmark_group("generation");
+
+    for spacing in [None, Some(5.)] {
+        let mut account = Account::new();
+        account.set_spacing(spacing).unwrap();
+        let name = if spacing.is_some() {
+            "resampled"
+        } else {
+            "route points"
+        };
+
+        for mileage in MILEAGES {
+            group.bench_with_input(BenchmarkId::new(name, mileage), &mileage, |b, &mileage| {
+                b.iter(|| account.export_track(black_box(&route), mileage).unwrap())
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, parsing, generation);
+criterion_main!(benches);
@@ -0,0 +1,74 @@
+/*
+    Pretty Der6y - A third-party running data upload client.
+    Copyright (C) 2024  Fay Ash
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published
+    by the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU Affero General Public License for more details.
+
+    You should have received a copy of the GNU Affero General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Passwords and sessions saved in the system keyring. On Linux they are
+//! kept in the Secret Service, e.g. GNOME Keyring or KWallet, and cached in
+//! the kernel keyring, which on its own would be cleared on reboot.
+
+use keyring::Entry;
+
+use crate::Session;
+
+use std::error::Error;
+
+const SERVICE: &str = "pretty-der6y";
+const SESSION_SERVICE: &str = "pretty-der6y-session";
+
+/// Saves the password for `username` in the system keyring, replacing any
+/// previously saved one.
+pub fn save(username: &str, password: &str) -> Result<(), Box<dyn Error>> {
+    Entry::new(SERVICE, username)?.set_password(password)?;
+    Ok(())
+}
+
+/// Loads the password saved for `username`, or `None` if nothing is saved.
+pub fn load(username: &str) -> Result<Option<String>, Box<dyn Error>> {
+    match Entry::new(SERVICE, username)?.get_password() {
+        Ok(password) => Ok(Some(password)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Saves `session` in the system keyring, replacing any previously saved
+/// one for its account.
+pub fn save_session(session: &Session) -> Result<(), Box<dyn Error>> {
+    Entry::new(SESSION_SERVICE, session.username())?
+        .set_password(&serde_json::to_string(session)?)?;
+    Ok(())
+}
+
+/// Loads the session saved for `username`, or `None` if nothing is saved.
+pub fn load_session(username: &str) -> Result<Option<Session>, Box<dyn Error>> {
+    match Entry::new(SESSION_SERVICE, username)?.get_password() {
+        Ok(session) => Ok(Some(serde_json::from_str(&session)?)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Removes the password and session saved for `username`, if any.
+pub fn forget(username: &str) -> Result<(), Box<dyn Error>> {
+    for service in [SERVICE, SESSION_SERVICE] {
+        match Entry::new(service, username)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
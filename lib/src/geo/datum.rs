@@ -0,0 +1,174 @@
+/*
+    Pretty Der6y - A third-party running data upload client.
+    Copyright (C) 2024  Fay Ash
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published
+    by the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU Affero General Public License for more details.
+
+    You should have received a copy of the GNU Affero General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// Semi-major axis of the Krasovsky 1940 ellipsoid used by GCJ-02.
+const A: f64 = 6378245.0;
+/// Eccentricity squared of the Krasovsky 1940 ellipsoid.
+const EE: f64 = 0.006_693_421_622_965_943;
+const X_PI: f64 = PI * 3000.0 / 180.0;
+
+/// Coordinate systems a route can be drawn in.
+///
+/// Chinese map services (Amap, Tencent, Baidu) offset their coordinates,
+/// while GPS devices and OpenStreetMap use WGS-84.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Datum {
+    Wgs84,
+    Gcj02,
+    Bd09,
+}
+
+impl FromStr for Datum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().replace('-', "").as_str() {
+            "WGS84" => Ok(Datum::Wgs84),
+            "GCJ02" => Ok(Datum::Gcj02),
+            "BD09" => Ok(Datum::Bd09),
+            _ => Err(format!("Unknown datum: {s:?}")),
+        }
+    }
+}
+
+impl Datum {
+    /// Converts a `(longitude, latitude)` pair in this datum to WGS-84.
+    pub fn to_wgs84(self, lon: f64, lat: f64) -> (f64, f64) {
+        match self {
+            Datum::Wgs84 => (lon, lat),
+            Datum::Gcj02 => gcj02_to_wgs84(lon, lat),
+            Datum::Bd09 => {
+                let (lon, lat) = bd09_to_gcj02(lon, lat);
+                gcj02_to_wgs84(lon, lat)
+            }
+        }
+    }
+}
+
+/// Projects a WGS-84 `(longitude, latitude)` pair to GCJ-02.
+///
+/// Coordinates outside China are returned unchanged.
+pub fn wgs84_to_gcj02(lon: f64, lat: f64) -> (f64, f64) {
+    if out_of_china(lon, lat) {
+        return (lon, lat);
+    }
+
+    let (dlon, dlat) = offset(lon, lat);
+    (lon + dlon, lat + dlat)
+}
+
+/// GCJ-02 has no closed-form inverse, so the offset is refined iteratively
+/// until it converges well below GPS precision.
+fn gcj02_to_wgs84(lon: f64, lat: f64) -> (f64, f64) {
+    if out_of_china(lon, lat) {
+        return (lon, lat);
+    }
+
+    let (mut wgs_lon, mut wgs_lat) = (lon, lat);
+    for _ in 0..10 {
+        let (gcj_lon, gcj_lat) = wgs84_to_gcj02(wgs_lon, wgs_lat);
+        let (dlon, dlat) = (gcj_lon - lon, gcj_lat - lat);
+        wgs_lon -= dlon;
+        wgs_lat -= dlat;
+        if dlon.abs() < 1e-9 && dlat.abs() < 1e-9 {
+            break;
+        }
+    }
+
+    (wgs_lon, wgs_lat)
+}
+
+fn bd09_to_gcj02(lon: f64, lat: f64) -> (f64, f64) {
+    let (x, y) = (lon - 0.0065, lat - 0.006);
+    let z = (x * x + y * y).sqrt() - 0.00002 * (y * X_PI).sin();
+    let theta = y.atan2(x) - 0.000003 * (x * X_PI).cos();
+    (z * theta.cos(), z * theta.sin())
+}
+
+/// GCJ-02 is only applied within the rough bounding box of China.
+fn out_of_china(lon: f64, lat: f64) -> bool {
+    !(72.004..=137.8347).contains(&lon) || !(0.8293..=55.8271).contains(&lat)
+}
+
+fn offset(lon: f64, lat: f64) -> (f64, f64) {
+    let (x, y) = (lon - 105.0, lat - 35.0);
+    let common = (20.0 * (6.0 * x * PI).sin() + 20.0 * (2.0 * x * PI).sin()) * 2.0 / 3.0;
+
+    let mut dlat = -100.0 + 2.0 * x + 3.0 * y + 0.2 * y * y + 0.1 * x * y + 0.2 * x.abs().sqrt();
+    dlat += common;
+    dlat += (20.0 * (y * PI).sin() + 40.0 * (y / 3.0 * PI).sin()) * 2.0 / 3.0;
+    dlat += (160.0 * (y / 12.0 * PI).sin() + 320.0 * (y * PI / 30.0).sin()) * 2.0 / 3.0;
+
+    let mut dlon = 300.0 + x + 2.0 * y + 0.1 * x * x + 0.1 * x * y + 0.1 * x.abs().sqrt();
+    dlon += common;
+    dlon += (20.0 * (x * PI).sin() + 40.0 * (x / 3.0 * PI).sin()) * 2.0 / 3.0;
+    dlon += (150.0 * (x / 12.0 * PI).sin() + 300.0 * (x / 30.0 * PI).sin()) * 2.0 / 3.0;
+
+    let radlat = lat / 180.0 * PI;
+    let magic = 1.0 - EE * radlat.sin() * radlat.sin();
+    let sqrtmagic = magic.sqrt();
+    dlat = (dlat * 180.0) / ((A * (1.0 - EE)) / (magic * sqrtmagic) * PI);
+    dlon = (dlon * 180.0) / (A / sqrtmagic * radlat.cos() * PI);
+
+    (dlon, dlat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A point in Shanghai and its published GCJ-02 projection.
+    const WGS84: (f64, f64) = (121.5272106, 31.1774276);
+    const GCJ02: (f64, f64) = (121.531541859215, 31.17530398364597);
+
+    fn assert_close(actual: (f64, f64), expected: (f64, f64), tolerance: f64) {
+        assert!(
+            (actual.0 - expected.0).abs() < tolerance && (actual.1 - expected.1).abs() < tolerance,
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    fn gcj02_to_bd09(lon: f64, lat: f64) -> (f64, f64) {
+        let z = (lon * lon + lat * lat).sqrt() + 0.00002 * (lat * X_PI).sin();
+        let theta = lat.atan2(lon) + 0.000003 * (lon * X_PI).cos();
+        (z * theta.cos() + 0.0065, z * theta.sin() + 0.006)
+    }
+
+    #[test]
+    fn test_gcj02_round_trip() {
+        assert_close(wgs84_to_gcj02(WGS84.0, WGS84.1), GCJ02, 1e-8);
+        assert_close(Datum::Gcj02.to_wgs84(GCJ02.0, GCJ02.1), WGS84, 1e-8);
+    }
+
+    #[test]
+    fn test_bd09_round_trip() {
+        let bd09 = gcj02_to_bd09(GCJ02.0, GCJ02.1);
+        // The BD-09 formulas are not exact inverses, but agree to ~5 cm
+        assert_close(bd09_to_gcj02(bd09.0, bd09.1), GCJ02, 1e-6);
+        assert_close(Datum::Bd09.to_wgs84(bd09.0, bd09.1), WGS84, 1e-6);
+    }
+
+    #[test]
+    fn test_out_of_china() {
+        assert_eq!(wgs84_to_gcj02(2.35, 48.85), (2.35, 48.85));
+        assert_eq!(Datum::Gcj02.to_wgs84(2.35, 48.85), (2.35, 48.85));
+    }
+}
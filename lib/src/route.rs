@@ -0,0 +1,583 @@
+/*
+    Pretty Der6y - A third-party running data upload client.
+    Copyright (C) 2024  Fay Ash
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published
+    by the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU Affero General Public License for more details.
+
+    You should have received a copy of the GNU Affero General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use chrono::{DateTime, FixedOffset};
+use log::warn;
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use serde_json::{json, Value};
+
+use std::error::Error;
+
+use crate::geo::datum::Datum;
+
+/// Normalizes a GeoJSON route into a `FeatureCollection` with exactly one
+/// `LineString` feature, the shape expected by `Account::upload_running`.
+///
+/// Bare `LineString` geometries, single `Feature`s and `FeatureCollection`s
+/// are accepted. `Point` features with a `radius` property in meters are
+/// kept as checkpoints the track must pass in order, other features (e.g.
+/// markers) are ignored. Properties like `name` and `distance` are kept on
+/// the resulting feature. Routes with a `datum` property of `GCJ-02` or
+/// `BD-09` are converted to WGS-84.
+pub fn normalize(geojson: &str) -> Result<String, Box<dyn Error>> {
+    let value: Value = serde_json::from_str(geojson)?;
+    let kind = value["type"].as_str().unwrap_or_default().to_owned();
+
+    let features = match kind.as_str() {
+        "FeatureCollection" => value["features"]
+            .as_array()
+            .cloned()
+            .ok_or("Invalid FeatureCollection")?,
+        "Feature" => vec![value],
+        "LineString" => vec![json!({ "type": "Feature", "geometry": value })],
+        _ => return Err(format!("Unsupported GeoJSON type: {kind:?}").into()),
+    };
+
+    let (mut lines, mut checkpoints) = (Vec::new(), Vec::new());
+    for feature in features {
+        match feature["geometry"]["type"].as_str() {
+            Some("LineString") => lines.push(feature),
+            Some("Point")
+                if feature["properties"]["radius"]
+                    .as_f64()
+                    .is_some_and(|radius| radius > 0.) =>
+            {
+                checkpoints.push(feature)
+            }
+            _ => {}
+        }
+    }
+
+    let mut feature = match <[_; 1]>::try_from(lines) {
+        Ok([feature]) => feature,
+        Err(lines) if lines.is_empty() => return Err("No LineString found in route".into()),
+        Err(_) => return Err("Route contains more than one LineString".into()),
+    };
+
+    let mut properties = match feature["properties"].take() {
+        properties @ Value::Object(_) => properties,
+        _ => json!({}),
+    };
+    let mut geometry = feature["geometry"].take();
+
+    let checkpoints = checkpoints
+        .into_iter()
+        .map(|mut checkpoint| -> Result<_, Box<dyn Error>> {
+            let radius = checkpoint["properties"]["radius"].take();
+            let mut geometry = checkpoint["geometry"].take();
+            if let Some(datum) = properties["datum"].as_str() {
+                to_wgs84(&mut geometry, datum.parse()?)?;
+            }
+
+            Ok(json!({
+                "type": "Feature",
+                "properties": { "radius": radius },
+                "geometry": geometry,
+            }))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if let Some(datum) = properties["datum"].as_str() {
+        to_wgs84(&mut geometry, datum.parse()?)?;
+        properties["datum"] = "WGS-84".into();
+    }
+
+    Ok(collection(properties, geometry, checkpoints))
+}
+
+/// Converts the first track of a GPX file into a normalized GeoJSON route.
+///
+/// Track segments are joined in order. When every track point has a time,
+/// points are put in time order and points repeating a time are dropped,
+/// which repairs common phone export artifacts. Files without tracks fall
+/// back to their first route, and the track name is kept as the `name`
+/// property.
+pub fn from_gpx(gpx: &str) -> Result<String, Box<dyn Error>> {
+    let mut reader = Reader::from_str(gpx);
+    let mut path = Vec::new();
+    let mut times = Vec::new();
+    let (mut tracks, mut routes) = (0, 0);
+    let (mut track, mut route) = ((None, Vec::new()), (None, Vec::new()));
+
+    loop {
+        let (element, empty) = match reader.read_event()? {
+            Event::Start(element) => (element, false),
+            Event::Empty(element) => (element, true),
+            Event::End(_) => {
+                path.pop();
+                continue;
+            }
+            Event::Text(text) => {
+                let text = text.unescape()?;
+                if tracks == 1 && at(&path, &["trk", "name"]) {
+                    track.0 = Some(text.trim().to_owned());
+                } else if tracks == 1 && at(&path, &["trkpt", "time"]) {
+                    if let Some(time) = times.last_mut() {
+                        *time = DateTime::parse_from_rfc3339(text.trim()).ok();
+                    }
+                } else if routes == 1 && at(&path, &["rte", "name"]) {
+                    route.0 = Some(text.trim().to_owned());
+                }
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        let name = element.local_name().as_ref().to_vec();
+        match name.as_slice() {
+            b"trk" => tracks += 1,
+            b"rte" => routes += 1,
+            b"trkpt" if tracks == 1 => {
+                track.1.push(position(&element)?);
+                times.push(None);
+            }
+            b"rtept" if routes == 1 => route.1.push(position(&element)?),
+            _ => {}
+        }
+        if !empty {
+            path.push(name);
+        }
+    }
+
+    let (name, coordinates) = match (tracks, routes) {
+        (0, 0) => return Err("No track found in GPX file".into()),
+        (0, _) => route,
+        _ => (track.0, by_time(track.1, times)),
+    };
+
+    line(name, coordinates)
+}
+
+/// Sorts points by time and drops points repeating the time before them,
+/// or keeps them as they are if any point has no time.
+fn by_time(points: Vec<[f64; 2]>, times: Vec<Option<DateTime<FixedOffset>>>) -> Vec<[f64; 2]> {
+    let Some(mut timed) = times
+        .into_iter()
+        .zip(&points)
+        .map(|(time, point)| Some((time?, *point)))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return points;
+    };
+
+    timed.sort_by_key(|(time, _)| *time);
+    timed.dedup_by_key(|(time, _)| *time);
+    if timed.len() != points.len() || timed.iter().zip(&points).any(|((_, a), b)| a != b) {
+        warn!("Repaired out of order or repeated GPX point times");
+    }
+
+    timed.into_iter().map(|(_, point)| point).collect()
+}
+
+/// Converts the first `LineString` of a KML file into a normalized GeoJSON
+/// route, keeping the name of its placemark.
+pub fn from_kml(kml: &str) -> Result<String, Box<dyn Error>> {
+    let mut reader = Reader::from_str(kml);
+    let mut path = Vec::new();
+    let mut name = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) => {
+                let element = element.local_name().as_ref().to_vec();
+                // Only keep the name of the placemark holding the line
+                if element == b"Placemark" {
+                    name = None;
+                }
+                path.push(element);
+            }
+            Event::End(_) => {
+                path.pop();
+            }
+            Event::Text(text) => {
+                let text = text.unescape()?;
+                if at(&path, &["Placemark", "name"]) {
+                    name = Some(text.trim().to_owned());
+                } else if at(&path, &["LineString", "coordinates"]) {
+                    let coordinates = text
+                        .split_whitespace()
+                        .map(|tuple| {
+                            let mut values = tuple.split(',').map(str::parse);
+                            match (values.next(), values.next()) {
+                                (Some(Ok(lon)), Some(Ok(lat))) => Ok([lon, lat]),
+                                _ => Err(format!("Invalid KML coordinate: {tuple}")),
+                            }
+                        })
+                        .collect::<Result<_, _>>()?;
+
+                    return line(name, coordinates);
+                }
+            }
+            Event::Eof => return Err("No LineString found in KML file".into()),
+            _ => {}
+        }
+    }
+}
+
+/// Converts the trackpoints of a TCX activity or course into a normalized
+/// GeoJSON route. Laps are joined in order and points without a position
+/// are skipped.
+pub fn from_tcx(tcx: &str) -> Result<String, Box<dyn Error>> {
+    let mut reader = Reader::from_str(tcx);
+    let mut path = Vec::new();
+    let mut name = None;
+    let mut coordinates = Vec::new();
+    let (mut lon, mut lat) = (None, None);
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) => path.push(element.local_name().as_ref().to_vec()),
+            Event::End(element) => {
+                if element.local_name().as_ref() == b"Trackpoint" {
+                    if let (Some(lon), Some(lat)) = (lon.take(), lat.take()) {
+                        coordinates.push([lon, lat]);
+                    }
+                }
+                path.pop();
+            }
+            Event::Text(text) => {
+                let text = text.unescape()?;
+                let degrees = || text.trim().parse::<f64>();
+                if at(&path, &["Position", "LongitudeDegrees"]) {
+                    lon = Some(degrees()?);
+                } else if at(&path, &["Position", "LatitudeDegrees"]) {
+                    lat = Some(degrees()?);
+                } else if name.is_none() && at(&path, &["Course", "Name"]) {
+                    name = Some(text.trim().to_owned());
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    line(name, coordinates)
+}
+
+/// Loads a route file, picking the parser from its extension.
+pub fn load(name: &str, content: &str) -> Result<String, Box<dyn Error>> {
+    let name = name.to_lowercase();
+    if name.ends_with(".gpx") {
+        from_gpx(content)
+    } else if name.ends_with(".kml") {
+        from_kml(content)
+    } else if name.ends_with(".tcx") {
+        from_tcx(content)
+    } else {
+        normalize(content)
+    }
+}
+
+/// Reads the `lon` and `lat` attributes of a GPX point.
+fn position(element: &BytesStart) -> Result<[f64; 2], Box<dyn Error>> {
+    let degrees = |key: &str| -> Result<f64, Box<dyn Error>> {
+        let attribute = element
+            .try_get_attribute(key)?
+            .ok_or(format!("GPX point without {key}"))?;
+        let value = attribute.unescape_value()?;
+        value
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid GPX {key}: {value}").into())
+    };
+
+    Ok([degrees("lon")?, degrees("lat")?])
+}
+
+/// Checks whether the innermost open elements match `tail`.
+fn at(path: &[Vec<u8>], tail: &[&str]) -> bool {
+    path.len() >= tail.len()
+        && path[path.len() - tail.len()..]
+            .iter()
+            .zip(tail)
+            .all(|(element, name)| element == name.as_bytes())
+}
+
+/// Converts the coordinates of a `LineString` or `Point` from `datum` to
+/// WGS-84.
+fn to_wgs84(geometry: &mut Value, datum: Datum) -> Result<(), Box<dyn Error>> {
+    if geometry["type"] == "Point" {
+        return convert(&mut geometry["coordinates"], datum);
+    }
+
+    let coordinates = geometry["coordinates"]
+        .as_array_mut()
+        .ok_or("Invalid LineString")?;
+
+    for coordinate in coordinates {
+        convert(coordinate, datum)?;
+    }
+
+    Ok(())
+}
+
+fn convert(coordinate: &mut Value, datum: Datum) -> Result<(), Box<dyn Error>> {
+    let Some([lon, lat, ..]) = coordinate.as_array_mut().map(Vec::as_mut_slice) else {
+        return Err("Invalid coordinate in route".into());
+    };
+    let (Some(x), Some(y)) = (lon.as_f64(), lat.as_f64()) else {
+        return Err("Invalid coordinate in route".into());
+    };
+
+    let (x, y) = datum.to_wgs84(x, y);
+    *lon = x.into();
+    *lat = y.into();
+
+    Ok(())
+}
+
+fn line(name: Option<String>, coordinates: Vec<[f64; 2]>) -> Result<String, Box<dyn Error>> {
+    if coordinates.len() < 2 {
+        return Err("Route has fewer than two points".into());
+    }
+
+    let properties = match name {
+        Some(name) => json!({ "name": name }),
+        None => json!({}),
+    };
+
+    Ok(collection(
+        properties,
+        json!({ "type": "LineString", "coordinates": coordinates }),
+        Vec::new(),
+    ))
+}
+
+fn collection(properties: Value, geometry: Value, checkpoints: Vec<Value>) -> String {
+    let mut features = vec![json!({
+        "type": "Feature",
+        "properties": properties,
+        "geometry": geometry,
+    })];
+    features.extend(checkpoints);
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COORDINATES: &str = "[[116.3,39.9],[116.31,39.91],[116.32,39.9]]";
+
+    fn feature(route: &str) -> Value {
+        let value: Value = serde_json::from_str(route).unwrap();
+        let features = value["features"].as_array().unwrap();
+        assert_eq!(value["type"], "FeatureCollection");
+        assert_eq!(features.len(), 1);
+        features[0].clone()
+    }
+
+    #[test]
+    fn test_normalize_line_string() {
+        let geojson = format!(r#"{{"type":"LineString","coordinates":{COORDINATES}}}"#);
+        let feature = feature(&normalize(&geojson).unwrap());
+        assert_eq!(
+            feature["geometry"]["coordinates"].as_array().unwrap().len(),
+            3
+        );
+        assert_eq!(feature["properties"], json!({}));
+    }
+
+    #[test]
+    fn test_normalize_feature() {
+        let geojson = format!(
+            r#"{{"type":"Feature","properties":{{"name":"Track"}},
+                "geometry":{{"type":"LineString","coordinates":{COORDINATES}}}}}"#
+        );
+        let feature = feature(&normalize(&geojson).unwrap());
+        assert_eq!(feature["properties"]["name"], "Track");
+    }
+
+    #[test]
+    fn test_normalize_feature_collection_with_marker() {
+        let geojson = format!(
+            r#"{{"type":"FeatureCollection","features":[
+                {{"type":"Feature","properties":{{}},
+                  "geometry":{{"type":"Point","coordinates":[116.3,39.9]}}}},
+                {{"type":"Feature","properties":{{"distance":2.1}},
+                  "geometry":{{"type":"LineString","coordinates":{COORDINATES}}}}}]}}"#
+        );
+        let feature = feature(&normalize(&geojson).unwrap());
+        assert_eq!(feature["geometry"]["type"], "LineString");
+        assert_eq!(feature["properties"]["distance"], 2.1);
+    }
+
+    #[test]
+    fn test_normalize_checkpoints() {
+        let geojson = format!(
+            r#"{{"type":"FeatureCollection","features":[
+                {{"type":"Feature","properties":{{"radius":20}},
+                  "geometry":{{"type":"Point","coordinates":[116.31,39.91]}}}},
+                {{"type":"Feature","properties":{{"datum":"GCJ-02"}},
+                  "geometry":{{"type":"LineString","coordinates":{COORDINATES}}}}}]}}"#
+        );
+        let value: Value = serde_json::from_str(&normalize(&geojson).unwrap()).unwrap();
+        let features = value["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+        assert_eq!(features[0]["geometry"]["type"], "LineString");
+        assert_eq!(features[1]["properties"]["radius"], 20);
+
+        // Converted to WGS-84 along with the line
+        let checkpoint = &features[1]["geometry"]["coordinates"];
+        assert_eq!(checkpoint, &features[0]["geometry"]["coordinates"][1]);
+    }
+
+    #[test]
+    fn test_normalize_two_line_strings() {
+        let line = format!(
+            r#"{{"type":"Feature","geometry":{{"type":"LineString","coordinates":{COORDINATES}}}}}"#
+        );
+        let geojson = format!(r#"{{"type":"FeatureCollection","features":[{line},{line}]}}"#);
+        let error = normalize(&geojson).unwrap_err();
+        assert_eq!(error.to_string(), "Route contains more than one LineString");
+    }
+
+    #[test]
+    fn test_normalize_idempotent() {
+        let geojson = format!(
+            r#"{{"type":"Feature","properties":{{"name":"Track"}},
+                "geometry":{{"type":"LineString","coordinates":{COORDINATES}}}}}"#
+        );
+        let once = normalize(&geojson).unwrap();
+        assert_eq!(normalize(&once).unwrap(), once);
+    }
+
+    #[test]
+    fn test_gpx_segments() {
+        let gpx = r#"<?xml version="1.0"?>
+<gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1">
+  <trk>
+    <name>Morning Run</name>
+    <trkseg>
+      <trkpt lat="39.9" lon="116.3"><ele>50</ele></trkpt>
+      <trkpt lat="39.91" lon="116.31"/>
+    </trkseg>
+    <trkseg>
+      <trkpt lat="39.92" lon="116.32"/>
+    </trkseg>
+  </trk>
+  <trk>
+    <name>Other</name>
+    <trkseg><trkpt lat="1" lon="1"/><trkpt lat="2" lon="2"/></trkseg>
+  </trk>
+</gpx>"#;
+        let feature = feature(&from_gpx(gpx).unwrap());
+        assert_eq!(feature["properties"]["name"], "Morning Run");
+        assert_eq!(
+            feature["geometry"]["coordinates"],
+            json!([[116.3, 39.9], [116.31, 39.91], [116.32, 39.92]])
+        );
+    }
+
+    #[test]
+    fn test_gpx_route_only() {
+        let gpx = r#"<gpx version="1.1">
+  <rte>
+    <name>Planned</name>
+    <rtept lat="39.9" lon="116.3"/>
+    <rtept lat="39.91" lon="116.31"/>
+  </rte>
+</gpx>"#;
+        let feature = feature(&from_gpx(gpx).unwrap());
+        assert_eq!(feature["properties"]["name"], "Planned");
+        assert_eq!(
+            feature["geometry"]["coordinates"],
+            json!([[116.3, 39.9], [116.31, 39.91]])
+        );
+    }
+
+    #[test]
+    fn test_gpx_times() {
+        let gpx = r#"<gpx version="1.1">
+  <trk><trkseg>
+    <trkpt lat="39.9" lon="116.3"><time>2024-09-19T07:00:00Z</time></trkpt>
+    <trkpt lat="39.92" lon="116.32"><time>2024-09-19T07:00:20Z</time></trkpt>
+    <trkpt lat="39.91" lon="116.31"><time>2024-09-19T15:00:10+08:00</time></trkpt>
+    <trkpt lat="39.93" lon="116.33"><time>2024-09-19T07:00:20Z</time></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+        let timed = feature(&from_gpx(gpx).unwrap());
+        assert_eq!(
+            timed["geometry"]["coordinates"],
+            json!([[116.3, 39.9], [116.31, 39.91], [116.32, 39.92]])
+        );
+
+        // Left alone unless every point has a time
+        let gpx = gpx.replace("<time>2024-09-19T07:00:00Z</time>", "");
+        let untimed = feature(&from_gpx(&gpx).unwrap());
+        assert_eq!(
+            untimed["geometry"]["coordinates"].as_array().unwrap().len(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_kml_with_altitude() {
+        let kml = r#"<kml xmlns="http://www.opengis.net/kml/2.2">
+  <Document>
+    <name>Document</name>
+    <Placemark><name>Start</name><Point><coordinates>116.3,39.9,0</coordinates></Point></Placemark>
+    <Placemark>
+      <name>Loop</name>
+      <LineString>
+        <coordinates>
+          116.3,39.9,50 116.31,39.91,51
+          116.32,39.92,52
+        </coordinates>
+      </LineString>
+    </Placemark>
+  </Document>
+</kml>"#;
+        let feature = feature(&from_kml(kml).unwrap());
+        assert_eq!(feature["properties"]["name"], "Loop");
+        assert_eq!(
+            feature["geometry"]["coordinates"],
+            json!([[116.3, 39.9], [116.31, 39.91], [116.32, 39.92]])
+        );
+    }
+
+    #[test]
+    fn test_tcx_without_position() {
+        let tcx = r#"<TrainingCenterDatabase>
+  <Activities><Activity Sport="Running"><Lap><Track>
+    <Trackpoint><Time>2024-09-20T08:00:00Z</Time><HeartRateBpm><Value>90</Value></HeartRateBpm></Trackpoint>
+    <Trackpoint>
+      <Position><LatitudeDegrees>39.9</LatitudeDegrees><LongitudeDegrees>116.3</LongitudeDegrees></Position>
+    </Trackpoint>
+    <Trackpoint><Time>2024-09-20T08:00:05Z</Time></Trackpoint>
+    <Trackpoint>
+      <Position><LatitudeDegrees>39.91</LatitudeDegrees><LongitudeDegrees>116.31</LongitudeDegrees></Position>
+    </Trackpoint>
+  </Track></Lap></Activity></Activities>
+</TrainingCenterDatabase>"#;
+        let feature = feature(&from_tcx(tcx).unwrap());
+        assert_eq!(
+            feature["geometry"]["coordinates"],
+            json!([[116.3, 39.9], [116.31, 39.91]])
+        );
+    }
+}
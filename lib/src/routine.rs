@@ -16,56 +16,19 @@
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use geo::{prelude::*, Point};
+use geo::{prelude::*, Closest, Line, Point};
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use std::{error::Error, f64::consts::PI};
 
-// WGS-84 to GCJ-02 (Mars Coordinate System) conversion
-// Only valid for coordinates within China.
-fn wgs84_to_gcj02(lat: f64, lon: f64) -> (f64, f64) {
-    const A: f64 = 6378245.;
-    const EE: f64 = 0.006_693_421_622_965_943;
-
-    fn transform_lat(x: f64, y: f64) -> f64 {
-        let mut ret = -100.0 + 2.0 * x + 3.0 * y + 0.2 * y * y + 0.1 * x * y + 0.2 * x.abs().sqrt();
-        ret += (20.0 * (6.0 * x * PI).sin() + 20.0 * (2.0 * x * PI).sin()) * 2.0 / 3.0;
-        ret += (20.0 * (y * PI).sin() + 40.0 * (y / 3.0 * PI).sin()) * 2.0 / 3.0;
-        ret += (160.0 * (y / 12.0 * PI).sin() + 320.0 * (y * PI / 30.0).sin()) * 2.0 / 3.0;
-        ret
-    }
-
-    fn transform_lon(x: f64, y: f64) -> f64 {
-        let mut ret = 300.0 + x + 2.0 * y + 0.1 * x * x + 0.1 * x * y + 0.1 * x.abs().sqrt();
-        ret += (20.0 * (6.0 * x * PI).sin() + 20.0 * (2.0 * x * PI).sin()) * 2.0 / 3.0;
-        ret += (20.0 * (x * PI).sin() + 40.0 * (x / 3.0 * PI).sin()) * 2.0 / 3.0;
-        ret += (150.0 * (x / 12.0 * PI).sin() + 300.0 * (x / 30.0 * PI).sin()) * 2.0 / 3.0;
-        ret
-    }
-
-    fn out_of_china(lat: f64, lon: f64) -> bool {
-        if !(72.004..=137.8347).contains(&lon) {
-            return true;
-        }
-        if !(0.8293..=55.8271).contains(&lat) {
-            return true;
-        }
-        false
-    }
+use crate::geo::datum::{wgs84_to_gcj02, Datum};
 
-    if out_of_china(lat, lon) {
-        return (lat, lon);
-    }
+const METERS_PER_DEGREE: f64 = 111_320.;
 
-    let mut dlat = transform_lat(lon - 105.0, lat - 35.0);
-    let mut dlon = transform_lon(lon - 105.0, lat - 35.0);
-    let radlat = lat / 180.0 * PI;
-    let magic = (1.0 - EE * radlat.sin() * radlat.sin()).sqrt();
-    dlat = (dlat * 180.0) / ((A * (1.0 - EE)) / (magic * magic) * PI);
-    dlon = (dlon * 180.0) / (A / magic * radlat.cos() * PI);
-    let mglat = lat + dlat;
-    let mglon = lon + dlon;
-    (mglat, mglon)
+/// Samples a standard normal value with the Box-Muller transform.
+fn gaussian(rng: &mut impl Rng) -> f64 {
+    let (u, v): (f64, f64) = (1. - rng.gen::<f64>(), rng.gen());
+    (-2. * u.ln()).sqrt() * (2. * PI * v).cos()
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -74,9 +37,71 @@ pub struct LGPoint {
     latitude: f64,
 }
 
-pub fn get_routine(mut mileage: f64, geojson_str: &str) -> Result<Vec<LGPoint>, Box<dyn Error>> {
-    let mut ponits = Vec::new();
-    let mut last = None;
+/// Adds points every `spacing` meters along `path`. Its own vertices are
+/// kept, so corners are not cut and the length stays the same.
+fn resample(path: &[Point], spacing: f64) -> Vec<Point> {
+    let mut samples = path[..1].to_vec();
+    let mut offset = spacing;
+    for pair in path.windows(2) {
+        let distance = pair[0].haversine_distance(&pair[1]);
+        while offset < distance {
+            samples.push(pair[0].haversine_intermediate(&pair[1], offset / distance));
+            offset += spacing;
+        }
+        offset -= distance;
+        samples.push(pair[1]);
+    }
+    samples
+}
+
+/// Checks that `path` passes within `radius` meters of each checkpoint, in
+/// order.
+fn check_checkpoints(path: &[Point], checkpoints: &[(Point, f64)]) -> Result<(), Box<dyn Error>> {
+    let mut pending = checkpoints.iter().enumerate().peekable();
+    for pair in path.windows(2) {
+        let segment = Line::new(pair[0], pair[1]);
+        while let Some((_, (checkpoint, radius))) = pending.peek() {
+            let distance = match segment.haversine_closest_point(checkpoint) {
+                Closest::Intersection(point) | Closest::SinglePoint(point) => {
+                    point.haversine_distance(checkpoint)
+                }
+                Closest::Indeterminate => f64::INFINITY,
+            };
+            if distance > *radius {
+                break;
+            }
+            pending.next();
+        }
+    }
+
+    match pending.next() {
+        Some((index, (_, radius))) => Err(format!(
+            "Track misses checkpoint {} (within {} m)",
+            index + 1,
+            radius
+        )
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Walks the route in `geojson_str` until exactly `mileage` km are covered,
+/// looping it as often as needed and ending part-way along the last segment.
+///
+/// The track must pass the route's checkpoints in order. With a `spacing` in
+/// meters, points are added evenly along the track, otherwise only the
+/// route's own points are used. Each point is moved by
+/// Gaussian noise with a standard deviation of `jitter` meters.
+pub fn get_routine(
+    mileage: f64,
+    geojson_str: &str,
+    jitter: f64,
+    spacing: Option<f64>,
+) -> Result<Vec<LGPoint>, Box<dyn Error>> {
+    if !(mileage.is_finite() && mileage > 0.) {
+        return Err("Mileage must be a positive distance".into());
+    }
+
     let mut rng = thread_rng();
     let geo_json: geojson::GeoJson = geojson_str.parse()?;
     let features = match geo_json {
@@ -84,37 +109,185 @@ pub fn get_routine(mut mileage: f64, geojson_str: &str) -> Result<Vec<LGPoint>,
         _ => return Err("Invalid GeoJSON".into()),
     };
 
-    let feature = features.first().ok_or("No feature found")?;
-    let geometry = feature.geometry.as_ref().ok_or("No geometry found")?;
-    let coordinates = match geometry.value {
-        geojson::Value::LineString(ref ls) => ls,
-        _ => return Err("Invalid geometry".into()),
-    };
+    let mut coordinates = None;
+    let mut checkpoints = Vec::new();
+    for feature in &features {
+        let geometry = feature.geometry.as_ref().ok_or("No geometry found")?;
+        match geometry.value {
+            geojson::Value::LineString(ref ls) if coordinates.is_none() => coordinates = Some(ls),
+            geojson::Value::Point(ref coord) => {
+                if let Some(radius) = feature.property("radius").and_then(|r| r.as_f64()) {
+                    let (x, y) = wgs84_to_gcj02(coord[0], coord[1]);
+                    checkpoints.push((Point::new(x, y), radius));
+                }
+            }
+            _ => return Err("Invalid geometry".into()),
+        }
+    }
+    let coordinates = coordinates.ok_or("No LineString found")?;
+
+    let route = coordinates
+        .iter()
+        .map(|coord| {
+            let (x, y) = wgs84_to_gcj02(coord[0], coord[1]);
+            Point::new(x, y)
+        })
+        .collect::<Vec<_>>();
+
+    let mut last = *route.first().ok_or("No coordinates found")?;
+    if route.iter().all(|point| *point == last) {
+        return Err("Route has no length".into());
+    }
+
+    let mut remaining = mileage * 1000.;
+    let mut points = vec![last];
+    for &point in route.iter().cycle().skip(1) {
+        if remaining <= 0. {
+            break;
+        }
+
+        let distance = last.haversine_distance(&point);
+        if distance >= remaining {
+            points.push(last.haversine_intermediate(&point, remaining / distance));
+            break;
+        }
+
+        remaining -= distance;
+        if distance > 0. {
+            points.push(point);
+        }
+        last = point;
+    }
 
-    if coordinates.is_empty() {
-        return Err("No coordinates found".into());
+    check_checkpoints(&points, &checkpoints)?;
+    if let Some(spacing) = spacing {
+        points = resample(&points, spacing);
     }
 
-    loop {
-        for coord in coordinates {
-            let (y, x) = wgs84_to_gcj02(coord[1], coord[0]);
-            let point = Point::new(x, y);
-            if last.is_none() {
-                last = Some(point);
+    Ok(points
+        .into_iter()
+        .map(|point| {
+            let (dx, dy) = (jitter * gaussian(&mut rng), jitter * gaussian(&mut rng));
+            LGPoint {
+                longitude: point.x() + dx / (METERS_PER_DEGREE * point.y().to_radians().cos()),
+                latitude: point.y() + dy / METERS_PER_DEGREE,
             }
+        })
+        .collect())
+}
 
-            let new = LGPoint {
-                longitude: point.x() + rng.gen_range(-5e-6..5e-6),
-                latitude: point.y() + rng.gen_range(-5e-6..5e-6),
-            };
-            mileage -= last.unwrap().geodesic_distance(&point) / 1000.;
-            last = Some(point);
+/// Converts a generated track back to WGS-84 as a GeoJSON `FeatureCollection`
+/// holding one `LineString`, which can be viewed on any map or loaded again
+/// as a route.
+pub fn to_geojson(points: &[LGPoint]) -> String {
+    let coordinates = points
+        .iter()
+        .map(|point| {
+            let (lon, lat) = Datum::Gcj02.to_wgs84(point.longitude, point.latitude);
+            [lon, lat]
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::json!({
+        "type": "FeatureCollection",
+        "features": [{
+            "type": "Feature",
+            "properties": { "datum": "WGS-84" },
+            "geometry": { "type": "LineString", "coordinates": coordinates },
+        }],
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTE: &str = r#"{"type":"FeatureCollection","features":[{"type":"Feature",
+        "properties":{},"geometry":{"type":"LineString",
+        "coordinates":[[116.3,39.9],[116.301,39.9],[116.301,39.901],[116.3,39.901],[116.3,39.9]]}}]}"#;
 
-            ponits.push(new);
+    fn length(points: &[LGPoint]) -> f64 {
+        points
+            .windows(2)
+            .map(|pair| {
+                let a = Point::new(pair[0].longitude, pair[0].latitude);
+                let b = Point::new(pair[1].longitude, pair[1].latitude);
+                a.haversine_distance(&b)
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_exact_mileage() {
+        // About 1.2 laps of a ~390 m loop
+        let points = get_routine(0.47, ROUTE, 0., None).unwrap();
+        assert!((length(&points) - 470.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_spacing() {
+        let points = get_routine(0.47, ROUTE, 0., Some(10.)).unwrap();
+        let gaps = points.windows(2).map(length).collect::<Vec<_>>();
 
-            if mileage <= 0. {
-                return Ok(ponits);
+        // Evenly spaced, except for the route's own corners in between
+        assert!(gaps.iter().all(|gap| *gap <= 10. + 1e-6));
+        assert!(gaps.iter().filter(|gap| **gap > 10. - 1e-6).count() >= gaps.len() - 12);
+        assert!((length(&points) - 470.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let with = |checkpoints: &[(f64, f64)]| {
+            let mut route: serde_json::Value = serde_json::from_str(ROUTE).unwrap();
+            for (lon, lat) in checkpoints {
+                route["features"]
+                    .as_array_mut()
+                    .unwrap()
+                    .push(serde_json::json!({
+                        "type": "Feature",
+                        "properties": { "radius": 15 },
+                        "geometry": { "type": "Point", "coordinates": [lon, lat] },
+                    }));
             }
+            route.to_string()
+        };
+
+        // Middle of the first and second sides of the loop
+        let first = (116.3005, 39.9);
+        let second = (116.301, 39.9005);
+        assert!(get_routine(0.2, &with(&[first, second]), 0., None).is_ok());
+        assert!(get_routine(0.1, &with(&[first, second]), 0., None).is_err());
+        assert!(get_routine(0.2, &with(&[(116.31, 39.91)]), 0., None).is_err());
+        // Order matters unless the track loops back past the first again
+        assert!(get_routine(0.2, &with(&[second, first]), 0., None).is_err());
+        assert!(get_routine(0.5, &with(&[second, first]), 0., None).is_ok());
+    }
+
+    #[test]
+    fn test_to_geojson() {
+        let points = get_routine(0.47, ROUTE, 0., None).unwrap();
+        let track: serde_json::Value = serde_json::from_str(&to_geojson(&points)).unwrap();
+        let coordinates = track["features"][0]["geometry"]["coordinates"]
+            .as_array()
+            .unwrap();
+
+        // Back in WGS-84, on top of the original route
+        assert_eq!(coordinates.len(), points.len());
+        assert!((coordinates[1][0].as_f64().unwrap() - 116.301).abs() < 1e-8);
+        assert!((coordinates[1][1].as_f64().unwrap() - 39.9).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_no_length() {
+        let route = ROUTE.replace("116.301", "116.3").replace("39.901", "39.9");
+        assert!(get_routine(1., &route, 0., None).is_err());
+    }
+
+    #[test]
+    fn test_invalid_mileage() {
+        for mileage in [0., -1., f64::INFINITY, f64::NAN] {
+            assert!(get_routine(mileage, ROUTE, 0., None).is_err());
         }
     }
 }
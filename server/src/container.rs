@@ -0,0 +1,177 @@
+/*
+    Pretty Der6y - A third-party running data upload client.
+    Copyright (C) 2024  Fay Ash
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published
+    by the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU Affero General Public License for more details.
+
+    You should have received a copy of the GNU Affero General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::status::Status;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use std::sync::{Arc, Mutex};
+
+/// Prefix of the environment variables options are also read from, e.g.
+/// `PRETTY_DER6Y_ACCOUNTS` for `--accounts`.
+const ENV_PREFIX: &str = "PRETTY_DER6Y_";
+
+/// Options without a value, set by their variable being `1` or `true`.
+const SWITCHES: &[&str] = &["dry-run", "exact", "forget", "log-json", "verbose"];
+
+pub const DOCKERFILE: &str = r#"# Build with `docker build --build-arg BACKEND=<HOST> .` from the repository
+FROM rust:1-bookworm AS build
+ARG BACKEND
+WORKDIR /src
+COPY . .
+RUN cargo build --release -p server
+
+FROM debian:bookworm-slim
+RUN apt-get update \
+    && apt-get install -y --no-install-recommends ca-certificates \
+    && rm -rf /var/lib/apt/lists/*
+COPY --from=build /src/target/release/server /usr/local/bin/server
+# There is no keyring in a container, so also set PRETTY_DER6Y_PASSWORD or
+# PRETTY_DER6Y_PASSWORD_COMMAND, and PRETTY_DER6Y_ACCOUNT(S) and _AT
+ENV PRETTY_DER6Y_LOG_JSON=1 \
+    PRETTY_DER6Y_LISTEN=0.0.0.0:8080
+EXPOSE 8080
+HEALTHCHECK CMD ["server", "status"]
+ENTRYPOINT ["server"]
+CMD ["daemon"]
+"#;
+
+/// Turns the `PRETTY_DER6Y_*` variables among `vars` into command line
+/// arguments, so a container can be configured entirely by environment.
+pub fn env_args(vars: impl Iterator<Item = (String, String)>) -> Vec<String> {
+    let mut args = vec![];
+    for (name, value) in vars {
+        let Some(option) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let option = option.to_lowercase().replace('_', "-");
+
+        if SWITCHES.contains(&option.as_str()) {
+            if matches!(value.as_str(), "1" | "true") {
+                args.push(format!("--{option}"));
+            }
+        } else {
+            args.extend([format!("--{option}"), value]);
+        }
+    }
+    args
+}
+
+/// Resolves once the process is asked to stop, by SIGTERM or Ctrl+C.
+pub async fn stop_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("signal handler is installed");
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Answers `GET /health` and `GET /status` on `listener` for as long as
+/// the daemon runs, for container health checks and `server status`.
+pub async fn serve(listener: TcpListener, status: Arc<Mutex<Status>>) {
+    loop {
+        if let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(respond(stream, status.clone()));
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream, status: Arc<Mutex<Status>>) {
+    let mut request = [0; 1024];
+    let Ok(len) = stream.read(&mut request).await else {
+        return;
+    };
+    let request = String::from_utf8_lossy(&request[..len]);
+    let (code, kind, body) = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/health"] => ("200 OK", "text/plain", "ok\n".to_owned()),
+        ["GET", "/status"] => (
+            "200 OK",
+            "application/json",
+            status.lock().unwrap().to_json().to_string(),
+        ),
+        _ => ("404 Not Found", "text/plain", "not found\n".to_owned()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {code}\r\nContent-Type: {kind}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_args() {
+        let vars = [
+            ("PRETTY_DER6Y_ACCOUNTS", "/data/accounts"),
+            ("PRETTY_DER6Y_DRY_RUN", "1"),
+            ("PRETTY_DER6Y_VERBOSE", "0"),
+            ("PRETTY_DER6Y_AT", "0 18 * * *"),
+            ("HOME", "/root"),
+        ];
+        assert_eq!(
+            env_args(
+                vars.into_iter()
+                    .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            ),
+            [
+                "--accounts",
+                "/data/accounts",
+                "--dry-run",
+                "--at",
+                "0 18 * * *"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let status = Arc::new(Mutex::new(Status::new()));
+        status.lock().unwrap().record("13800000000", 1, None);
+        tokio::spawn(serve(listener, status));
+
+        for (path, status) in [("/health", "200"), ("/", "404")] {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(format!("GET {path} HTTP/1.1\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with(&format!("HTTP/1.1 {status}")));
+        }
+
+        let status = crate::status::fetch(&addr.to_string()).await.unwrap();
+        assert_eq!(status["state"], "waiting");
+        assert_eq!(status["results"]["13800000000"]["ok"], true);
+    }
+}
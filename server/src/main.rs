@@ -16,9 +16,1031 @@
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::error::Error;
+mod container;
+mod schedule;
+mod status;
+mod support;
+
+use chrono::{Duration, Local, NaiveTime};
+use lib::{credentials, route, Account, Session};
+use log::{debug, error, info, warn, Level, LevelFilter, Metadata, Record};
+use rand::{thread_rng, Rng};
+use schedule::Schedule;
+use serde_json::{json, Value};
+use status::{State, Status};
+use std::{
+    env,
+    error::Error,
+    fs,
+    io::{self, IsTerminal, Write},
+    iter,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{net::TcpListener, sync::watch};
+
+const USAGE: &str = "\
+Usage: server --account <ACCOUNT> [--password <PASSWORD>] [OPTIONS]
+       server --accounts <FILE> [OPTIONS]
+       server daemon (--account <ACCOUNT> | --accounts <FILE>) --at <SCHEDULE> [OPTIONS]
+       server selftest [--account <ACCOUNT>] [OPTIONS]
+       server status --listen <ADDR> [--output json]
+       server print-dockerfile
+
+Logs in and uploads one run without the GUI. `daemon` keeps running and
+uploads at every time of a cron-like schedule. `selftest` checks the route
+and track generation, and the login if an account is given, without
+uploading anything. `status` asks a daemon started with --listen what it
+is doing, when it uploads next and how its last uploads went.
+`print-dockerfile` prints a Dockerfile running the daemon in a container.
+
+An accounts file lists one account per line, optionally followed by the
+mileage to upload for it, e.g. `13800000000 2.5`. Lines starting with `#`
+are ignored. Passwords are taken from the system keyring, so log in with
+--account and --password once for each account to save them, or from
+--password-command.
+
+Sessions are saved in the system keyring after logging in and reused for
+a day without the password, unless --password is given.
+
+Every option can also be set by an environment variable, e.g.
+PRETTY_DER6Y_ACCOUNTS for --accounts, or PRETTY_DER6Y_DRY_RUN=1 for
+--dry-run. Options on the command line take precedence.
+
+Options:
+  --account <ACCOUNT>    Phone number used to log in
+  --accounts <FILE>      Upload for every account in FILE, one after another
+  --password <PASSWORD>  Password used to log in, defaults to the saved one
+  --password-command <COMMAND>
+                         Get the password from the first line printed by a
+                         shell command run with ACCOUNT set to the account,
+                         e.g. \"pass show der6y/$ACCOUNT\"
+  --mileage <KM>         Distance to upload, defaults to the daily limit
+  --pace <MIN/KM>        Average pace, defaults to 6
+  --window <HH:MM-HH:MM> Refuse runs not within this time of day
+  --exact                Upload the exact mileage and pace, without adding
+                         noise to the distance and duration
+  --noise <M>            Move each point by random noise of about M meters,
+                         defaults to 0.5, 0 turns it off
+  --spacing <M>          Resample tracks to points M meters apart, defaults to
+                         the route's own points
+  --route <FILE>         GeoJSON, GPX, KML or TCX route, defaults to the built-in one
+  --at <SCHEDULE>        Minute, hour, day, month and weekday to upload at,
+                         e.g. \"0 18 * * 1,3,5\"
+  --jitter <MIN>         Random delay after each scheduled time, defaults to 15
+  --attempts <N>         Scheduled uploads tried for each account before
+                         waiting for the next scheduled time, defaults to 3
+  --attempt-delay <MIN>  Delay before trying a failed scheduled upload again,
+                         defaults to 30
+  --listen <ADDR>        Answer GET /health and GET /status on ADDR while the
+                         daemon runs, e.g. 0.0.0.0:8080
+  --output <FORMAT>      Print `status` as text or json, defaults to text
+  --retries <N>          Tries per request on timeouts, connection errors and
+                         server errors, defaults to 3
+  --retry-delay <MS>     Delay before the first retry, doubling after each,
+                         defaults to 500
+  --proxy <URL>          HTTP or SOCKS proxy, repeat to spread accounts over
+                         several proxies in turn
+  --dry-run              Log in and build the run, but print it instead of uploading
+  --forget               Remove the saved password and exit
+  --verbose              Log requests and responses
+  --log-json             Print log records as JSON lines with the time, level,
+                         target and message, for log collectors
+  --support-bundle <FILE>
+                         Write the full log to FILE with accounts, passwords
+                         and tokens removed, to attach to bug reports
+  --help                 Print this help";
+
+struct StdoutLogger {
+    verbose: AtomicBool,
+    json: AtomicBool,
+    // Every record, kept only while a support bundle is requested
+    transcript: Mutex<Option<Vec<String>>>,
+}
+
+impl log::Log for StdoutLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if record.level() <= Level::Info || self.verbose.load(Ordering::Relaxed) {
+            if self.json.load(Ordering::Relaxed) {
+                println!("{}", json_line(record));
+            } else {
+                println!("{} - {}", record.level(), record.args());
+            }
+        }
+        if let Some(transcript) = self.transcript.lock().unwrap().as_mut() {
+            transcript.push(format!(
+                "{} {} - {}",
+                Local::now().format("%H:%M:%S%.3f"),
+                record.level(),
+                record.args()
+            ));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Formats `record` as a single line of JSON.
+fn json_line(record: &Record) -> String {
+    json!({
+        "timestamp": Local::now().to_rfc3339(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    })
+    .to_string()
+}
+
+static LOGGER: StdoutLogger = StdoutLogger {
+    verbose: AtomicBool::new(false),
+    json: AtomicBool::new(false),
+    transcript: Mutex::new(None),
+};
+
+const REPL_HELP: &str = "\
+login <ACCOUNT> [PASSWORD]  Log in, with the saved password if none is given
+get <PATH>                  Send a GET request, e.g. get /education/semester/getCurrent
+post <PATH> [JSON]          Send a POST request with a JSON body
+help                        Print this help
+quit                        Exit";
+
+const DAEMON_JITTER: u32 = 15;
+const DAEMON_ATTEMPTS: u32 = 3;
+const DAEMON_ATTEMPT_DELAY: u32 = 30;
+// Scheduled times reported by `status`
+const STATUS_RUNS: usize = 3;
+const SELFTEST_MILEAGE: f64 = 2.;
+
+#[derive(Debug, Default)]
+struct Args {
+    account: String,
+    accounts: Option<PathBuf>,
+    password: Option<String>,
+    password_command: Option<String>,
+    forget: bool,
+    dry_run: bool,
+    proxies: Vec<String>,
+    retries: Option<u32>,
+    retry_delay: Option<u64>,
+    mileage: Option<f64>,
+    pace: Option<f64>,
+    window: Option<(NaiveTime, NaiveTime)>,
+    exact: bool,
+    noise: Option<f64>,
+    spacing: Option<f64>,
+    route: Option<PathBuf>,
+    verbose: bool,
+    log_json: bool,
+    support_bundle: Option<PathBuf>,
+    selftest: bool,
+    repl: bool,
+    daemon: bool,
+    at: Option<Schedule>,
+    jitter: Option<u32>,
+    attempts: Option<u32>,
+    attempt_delay: Option<u32>,
+    listen: Option<String>,
+    status: bool,
+    output_json: bool,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {arg}"));
+            match arg.as_str() {
+                "--account" => parsed.account = value()?,
+                "--accounts" => parsed.accounts = Some(value()?.into()),
+                "--password" => parsed.password = Some(value()?),
+                "--password-command" => parsed.password_command = Some(value()?),
+                "--mileage" => {
+                    let mileage = value()?;
+                    parsed.mileage =
+                        Some(parse_mileage(&mileage).ok_or(format!("Invalid mileage: {mileage}"))?);
+                }
+                "--pace" => {
+                    let pace = value()?;
+                    parsed.pace = Some(pace.parse().map_err(|_| format!("Invalid pace: {pace}"))?);
+                }
+                "--window" => {
+                    let window = value()?;
+                    parsed.window =
+                        Some(parse_window(&window).ok_or(format!("Invalid window: {window}"))?);
+                }
+                "--exact" => parsed.exact = true,
+                "--noise" => {
+                    let noise = value()?;
+                    parsed.noise = Some(
+                        noise
+                            .parse()
+                            .map_err(|_| format!("Invalid noise: {noise}"))?,
+                    );
+                }
+                "--spacing" => {
+                    let spacing = value()?;
+                    parsed.spacing = Some(
+                        spacing
+                            .parse()
+                            .map_err(|_| format!("Invalid spacing: {spacing}"))?,
+                    );
+                }
+                "--route" => parsed.route = Some(value()?.into()),
+                "--forget" => parsed.forget = true,
+                "--dry-run" => parsed.dry_run = true,
+                "--proxy" => parsed.proxies.push(value()?),
+                "--retries" => {
+                    let retries = value()?;
+                    parsed.retries = Some(
+                        retries
+                            .parse()
+                            .ok()
+                            .filter(|retries| *retries > 0)
+                            .ok_or(format!("Invalid retries: {retries}"))?,
+                    );
+                }
+                "--retry-delay" => {
+                    let delay = value()?;
+                    parsed.retry_delay = Some(
+                        delay
+                            .parse()
+                            .map_err(|_| format!("Invalid retry delay: {delay}"))?,
+                    );
+                }
+                "--verbose" => parsed.verbose = true,
+                "--log-json" => parsed.log_json = true,
+                "--support-bundle" => parsed.support_bundle = Some(value()?.into()),
+                "selftest" => parsed.selftest = true,
+                "repl" => parsed.repl = true,
+                "daemon" => parsed.daemon = true,
+                "--at" => parsed.at = Some(value()?.parse()?),
+                "--jitter" => {
+                    let jitter = value()?;
+                    parsed.jitter = Some(
+                        jitter
+                            .parse()
+                            .map_err(|_| format!("Invalid jitter: {jitter}"))?,
+                    );
+                }
+                "--attempts" => {
+                    let attempts = value()?;
+                    parsed.attempts = Some(
+                        attempts
+                            .parse()
+                            .ok()
+                            .filter(|attempts| *attempts > 0)
+                            .ok_or(format!("Invalid attempts: {attempts}"))?,
+                    );
+                }
+                "--attempt-delay" => {
+                    let delay = value()?;
+                    parsed.attempt_delay = Some(
+                        delay
+                            .parse()
+                            .map_err(|_| format!("Invalid attempt delay: {delay}"))?,
+                    );
+                }
+                "--listen" => parsed.listen = Some(value()?),
+                "status" => parsed.status = true,
+                "--output" => {
+                    parsed.output_json = match value()?.as_str() {
+                        "json" => true,
+                        "text" => false,
+                        output => return Err(format!("Invalid output: {output}")),
+                    }
+                }
+                "print-dockerfile" => {
+                    print!("{}", container::DOCKERFILE);
+                    process::exit(0);
+                }
+                "--help" => {
+                    println!("{USAGE}");
+                    process::exit(0);
+                }
+                _ => return Err(format!("Unknown argument: {arg}")),
+            }
+        }
+
+        if parsed.account.is_empty()
+            && parsed.accounts.is_none()
+            && !parsed.selftest
+            && !parsed.repl
+            && !parsed.status
+        {
+            return Err("--account or --accounts is required".into());
+        }
+        if parsed.forget && parsed.account.is_empty() {
+            return Err("--forget needs --account".into());
+        }
+        if parsed.accounts.is_some() && parsed.password.is_some() {
+            return Err("--accounts takes passwords from the keyring, not --password".into());
+        }
+        if parsed.daemon && parsed.at.is_none() {
+            return Err("daemon needs a schedule, pass --at".into());
+        }
+        Ok(parsed)
+    }
+}
+
+/// Parses a distance in km, which must be positive. `f64` also parses
+/// `inf` and `NaN`, which no route could ever cover.
+fn parse_mileage(mileage: &str) -> Option<f64> {
+    mileage
+        .parse()
+        .ok()
+        .filter(|mileage: &f64| mileage.is_finite() && *mileage > 0.)
+}
+
+/// Parses a time of day window like `06:30-07:30`.
+fn parse_window(window: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let (from, to) = window.split_once('-')?;
+    let from = NaiveTime::parse_from_str(from, "%H:%M").ok()?;
+    let to = NaiveTime::parse_from_str(to, "%H:%M").ok()?;
+    (from < to).then_some((from, to))
+}
+
+fn confirm(question: &str) -> io::Result<bool> {
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
+
+fn load_route(args: &Args) -> Result<String, Box<dyn Error>> {
+    match &args.route {
+        Some(path) => route::load(&path.to_string_lossy(), &fs::read_to_string(path)?),
+        None => route::normalize(include_str!("../../assets/map.geojson")),
+    }
+}
+
+/// Loads the saved password of `account`, taking an unusable keyring, e.g.
+/// in a container, as no saved password.
+fn saved_password(account: &str) -> Option<String> {
+    credentials::load(account).unwrap_or_else(|e| {
+        warn!("Could not read the system keyring: {}", e);
+        None
+    })
+}
+
+/// Runs `command` through the shell with `ACCOUNT` set to `account` and
+/// takes the first line it prints as the password, like mbsync's PassCmd.
+fn command_password(command: &str, account: &str) -> Result<String, Box<dyn Error>> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let output = process::Command::new(shell)
+        .args([flag, command])
+        .env("ACCOUNT", account)
+        .stderr(process::Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("Password command failed: {}", output.status).into());
+    }
+
+    let output = String::from_utf8(output.stdout)?;
+    match output.lines().next() {
+        Some(password) if !password.is_empty() => Ok(password.to_owned()),
+        _ => Err("Password command printed no password".into()),
+    }
+}
+
+fn password(args: &Args, account: &str) -> Result<String, Box<dyn Error>> {
+    if let Some(password) = &args.password {
+        return Ok(password.clone());
+    }
+    if let Some(command) = &args.password_command {
+        return command_password(command, account);
+    }
+    saved_password(account).ok_or_else(|| {
+        format!("No saved password for {account}, log in with --account and --password once").into()
+    })
+}
+
+/// Runs every stage up to the upload and reports each one, returning
+/// whether all of them passed.
+async fn selftest(args: &Args) -> bool {
+    let mut passed = true;
+    let mut report = |stage: &str, result: Result<String, Box<dyn Error>>| match result {
+        Ok(detail) => println!("PASS {stage}: {detail}"),
+        Err(e) => {
+            println!("FAIL {stage}: {e}");
+            passed = false;
+        }
+    };
+
+    let route = match load_route(args) {
+        Ok(route) => route,
+        Err(e) => {
+            report("route", Err(e));
+            return false;
+        }
+    };
+    report("route", Ok("parsed".into()));
+
+    let mut account = Account::new();
+    if let Err(e) = configure(args, &mut account) {
+        report("options", Err(e));
+        return false;
+    }
+    let mileage = args.mileage.unwrap_or(SELFTEST_MILEAGE);
+    let track = account.export_track(&route, mileage).and_then(|track| {
+        let track: Value = serde_json::from_str(&track)?;
+        let points = track["features"][0]["geometry"]["coordinates"]
+            .as_array()
+            .map_or(0, Vec::len);
+        if points < 2 {
+            return Err("Track has fewer than 2 points".into());
+        }
+        Ok(format!("{points} points for {mileage:.2} km"))
+    });
+    report("track", track);
+
+    if !args.account.is_empty() {
+        let login = match password(args, &args.account) {
+            Ok(password) => account
+                .login(&args.account, &password)
+                .await
+                .map(|()| format!("daily limit {:.2} km", account.daily())),
+            Err(e) => Err(e),
+        };
+        report("login", login);
+    }
+
+    passed
+}
+
+/// An account to upload for, with the mileage to upload if not the daily
+/// limit.
+struct Target {
+    account: String,
+    mileage: Option<f64>,
+}
+
+/// Lists the accounts to upload for, from --accounts or --account.
+fn targets(args: &Args) -> Result<Vec<Target>, Box<dyn Error>> {
+    let Some(path) = &args.accounts else {
+        return Ok(vec![Target {
+            account: args.account.clone(),
+            mileage: args.mileage,
+        }]);
+    };
+
+    let mut targets = vec![];
+    for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = || format!("Invalid line {} in accounts file: {}", number + 1, line);
+        let mut fields = line.split_whitespace();
+        let account = fields.next().ok_or_else(invalid)?.to_owned();
+        let mileage = match fields.next() {
+            Some(mileage) => Some(parse_mileage(mileage).ok_or_else(invalid)?),
+            None => args.mileage,
+        };
+        if fields.next().is_some() {
+            return Err(invalid().into());
+        }
+        targets.push(Target { account, mileage });
+    }
+
+    if targets.is_empty() {
+        return Err("No accounts in accounts file".into());
+    }
+    Ok(targets)
+}
+
+/// Applies the options shaping generated runs to `account`.
+fn configure(args: &Args, account: &mut Account) -> Result<(), Box<dyn Error>> {
+    if let Some(pace) = args.pace {
+        account.set_pace(pace * 60.)?;
+    }
+    account.set_window(args.window);
+    account.set_humanize(!args.exact);
+    account.set_spacing(args.spacing)?;
+    if let Some(noise) = args.noise {
+        account.set_jitter(noise)?;
+    }
+    account.set_retry(
+        args.retries.unwrap_or(lib::RETRY_ATTEMPTS),
+        args.retry_delay.unwrap_or(lib::RETRY_DELAY),
+    );
+    Ok(())
+}
+
+/// Saves the session of `account` unless it is still `saved`, e.g. after
+/// the token was renewed by logging in again.
+fn save_session(account: &Account, saved: Option<&Session>) {
+    let Some(session) = account.session() else {
+        return;
+    };
+    if saved == Some(&session) {
+        return;
+    }
+    if let Err(e) = credentials::save_session(&session) {
+        warn!("Could not save the session: {}", e);
+    }
+}
+
+/// Resumes the saved session of `account`, returning whether it worked.
+/// The saved password, if any, renews the token when it is refused.
+async fn resume(account: &mut Account, username: &str) -> bool {
+    let session = match credentials::load_session(username) {
+        Ok(Some(session)) => session,
+        Ok(None) => return false,
+        Err(e) => {
+            debug!("Could not load the saved session: {}", e);
+            return false;
+        }
+    };
+
+    let (saved, password) = (session.clone(), saved_password(username));
+    match account.resume(session, password.as_deref()).await {
+        Ok(()) => {
+            info!("Resumed the saved session for {}", username);
+            save_session(account, Some(&saved));
+            true
+        }
+        Err(e) => {
+            debug!("Could not resume the saved session: {}", e);
+            false
+        }
+    }
+}
+
+async fn upload(args: &Args, target: &Target, proxy: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let route = load_route(args)?;
+
+    let mut account = Account::new();
+    account.set_proxy(proxy)?;
+    configure(args, &mut account)?;
+
+    // An explicit password always logs in, e.g. after changing it
+    if args.password.is_some() || !resume(&mut account, &target.account).await {
+        let password = &password(args, &target.account)?;
+        account.login(&target.account, password).await?;
+
+        if args.password.is_some()
+            && !args.daemon
+            && io::stdin().is_terminal()
+            && saved_password(&target.account).as_ref() != Some(password)
+            && confirm("Save the password to the system keyring?")?
+        {
+            if let Err(e) = credentials::save(&target.account, password) {
+                warn!("Could not save the password: {}", e);
+            }
+        }
+
+        save_session(&account, None);
+    }
+
+    let mileage = target.mileage.unwrap_or(account.daily());
+    if args.dry_run {
+        let summary = account.preview_running(&route, mileage, Local::now(), None)?;
+        info!("Dry run for {}, not uploading: {}", target.account, summary);
+        return Ok(());
+    }
+
+    info!("Uploading {:.2} km for {}...", mileage, target.account);
+
+    let session = account.session();
+    let uploaded = account
+        .upload_running(&route, mileage, Local::now(), None)
+        .await;
+    save_session(&account, session.as_ref());
+    uploaded
+}
+
+/// Picks the proxy for the target at `index`, going round the proxies so
+/// consecutive accounts use different ones.
+fn proxy(args: &Args, index: usize) -> Option<&str> {
+    (!args.proxies.is_empty()).then(|| args.proxies[index % args.proxies.len()].as_str())
+}
+
+/// The result of uploading for a target.
+type Uploaded<'a> = (&'a Target, Result<(), Box<dyn Error>>);
+
+/// Uploads for each of `targets` in turn and returns how each went. A
+/// failed upload is logged and does not stop the ones after it, but a
+/// requested stop does.
+async fn upload_each<'a>(
+    args: &Args,
+    targets: Vec<&'a Target>,
+    stopped: Option<&watch::Receiver<bool>>,
+) -> Vec<Uploaded<'a>> {
+    let mut results = Vec::new();
+    for (index, target) in targets.into_iter().enumerate() {
+        if stopped.is_some_and(|stopped| *stopped.borrow()) {
+            break;
+        }
+        if let Some(proxy) = proxy(args, index) {
+            info!("Using proxy {} for {}", proxy, target.account);
+        }
+        let result = upload(args, target, proxy(args, index)).await;
+        if let Err(e) = &result {
+            error!("Upload for {} failed: {}", target.account, e);
+        }
+        results.push((target, result));
+    }
+    results
+}
+
+/// Uploads for every target in turn.
+async fn upload_all(args: &Args) -> Result<(), Box<dyn Error>> {
+    let targets = targets(args)?;
+    if let [target] = &targets[..] {
+        return upload(args, target, proxy(args, 0)).await;
+    }
+
+    let results = upload_each(args, targets.iter().collect(), None).await;
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    if failed > 0 {
+        return Err(format!("{} of {} uploads failed", failed, targets.len()).into());
+    }
+    Ok(())
+}
+
+/// Records the `attempt`th `results` in `status` and returns the targets
+/// whose upload failed.
+fn record<'a>(status: &Mutex<Status>, attempt: u32, results: Vec<Uploaded<'a>>) -> Vec<&'a Target> {
+    let mut status = status.lock().unwrap();
+    let failed: Vec<_> = results
+        .into_iter()
+        .filter_map(|(target, result)| {
+            let error = result.err().map(|e| e.to_string());
+            let failed = error.is_some();
+            status.record(&target.account, attempt, error);
+            failed.then_some(target)
+        })
+        .collect();
+    status.queue = failed.len();
+    failed
+}
+
+/// Sleeps for `delay`, returning false instead as soon as a stop is
+/// requested.
+async fn wait(delay: std::time::Duration, stopped: &mut watch::Receiver<bool>) -> bool {
+    tokio::select! {
+        biased;
+        _ = stopped.wait_for(|stopped| *stopped) => false,
+        _ = tokio::time::sleep(delay) => true,
+    }
+}
+
+/// Uploads at every time `schedule` fires, each delayed by up to the
+/// jitter. Accounts whose upload failed are tried again after the attempt
+/// delay, up to the number of attempts, before the next time is waited for.
+/// SIGTERM or Ctrl+C stops the daemon once the upload in progress is done.
+/// What it is doing is kept for `status`.
+async fn daemon(args: &Args, schedule: &Schedule) -> Result<(), Box<dyn Error>> {
+    // Catch a bad route or a missing password before the first wait
+    load_route(args)?;
+    for target in targets(args)? {
+        password(args, &target.account)?;
+    }
+
+    let status = Arc::new(Mutex::new(Status::new()));
+    if let Some(addr) = &args.listen {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Answering health and status requests on {}", addr);
+        tokio::spawn(container::serve(listener, status.clone()));
+    }
+
+    let (stop, mut stopped) = watch::channel(false);
+    tokio::spawn({
+        let status = status.clone();
+        async move {
+            container::stop_signal().await;
+            info!("Stopping once no upload is in progress");
+            status.lock().unwrap().state = State::Stopping;
+            let _ = stop.send(true);
+        }
+    });
+
+    let jitter = args.jitter.unwrap_or(DAEMON_JITTER) as i64 * 60;
+    let attempts = args.attempts.unwrap_or(DAEMON_ATTEMPTS);
+    let attempt_delay = args.attempt_delay.unwrap_or(DAEMON_ATTEMPT_DELAY);
+    loop {
+        let runs: Vec<_> = iter::successors(schedule.next_after(Local::now()), |time| {
+            schedule.next_after(*time)
+        })
+        .take(STATUS_RUNS)
+        .collect();
+        let next = *runs.first().ok_or("Schedule never fires")?
+            + Duration::seconds(thread_rng().gen_range(0..=jitter));
+        info!("Next upload at {}", next.format("%Y-%m-%d %H:%M:%S"));
+        {
+            let mut status = status.lock().unwrap();
+            status.next_upload = Some(next);
+            status.next_runs = runs;
+        }
+
+        let delay = (next - Local::now()).to_std().unwrap_or_default();
+        if !wait(delay, &mut stopped).await {
+            break;
+        }
+        status.lock().unwrap().state = State::Uploading;
+
+        let targets = match targets(args) {
+            Ok(targets) => targets,
+            Err(e) => {
+                error!("Scheduled upload failed: {}", e);
+                continue;
+            }
+        };
+        let results = upload_each(args, targets.iter().collect(), Some(&stopped)).await;
+        let mut failed = record(&status, 1, results);
+        for attempt in 2..=attempts {
+            if failed.is_empty() || *stopped.borrow() {
+                break;
+            }
+            warn!(
+                "{} of {} scheduled uploads failed, trying them again in {} min ({}/{})",
+                failed.len(),
+                targets.len(),
+                attempt_delay,
+                attempt,
+                attempts
+            );
+            status.lock().unwrap().state = State::Retrying;
+            let delay = std::time::Duration::from_secs(attempt_delay as u64 * 60);
+            if !wait(delay, &mut stopped).await {
+                break;
+            }
+            status.lock().unwrap().state = State::Uploading;
+            let results = upload_each(args, failed, Some(&stopped)).await;
+            failed = record(&status, attempt, results);
+        }
+
+        if *stopped.borrow() {
+            break;
+        }
+        {
+            let mut status = status.lock().unwrap();
+            status.state = State::Waiting;
+            status.queue = 0;
+        }
+        match failed.len() {
+            0 => info!("Scheduled upload succeeded"),
+            count => error!(
+                "{} of {} scheduled uploads failed after {} attempts, waiting for the next time",
+                count,
+                targets.len(),
+                attempts
+            ),
+        }
+    }
+
+    info!("Stopped");
+    Ok(())
+}
+
+/// Prints the status of the daemon answering on --listen.
+async fn show_status(args: &Args) -> Result<(), Box<dyn Error>> {
+    let addr = args
+        .listen
+        .as_deref()
+        .ok_or("status needs the address the daemon listens on, pass --listen")?;
+    let report = status::fetch(addr)
+        .await
+        .map_err(|e| format!("Could not get the status of the daemon on {addr}: {e}"))?;
+    if args.output_json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", status::describe(&report));
+    }
+    Ok(())
+}
+
+async fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    if args.status {
+        return show_status(args).await;
+    }
+    if args.forget {
+        credentials::forget(&args.account)?;
+        info!("Removed the saved password for {}", args.account);
+        return Ok(());
+    }
+
+    match &args.at {
+        Some(schedule) if args.daemon => daemon(args, schedule).await,
+        _ => upload_all(args).await,
+    }
+}
+
+/// Writes the records kept by the logger to `path`, without the accounts
+/// and password in use.
+fn write_support_bundle(args: &Args, path: &Path) {
+    let transcript = LOGGER.transcript.lock().unwrap().take().unwrap_or_default();
+    let mut secrets = vec![args.account.clone()];
+    secrets.extend(args.password.clone());
+    secrets.extend(
+        targets(args)
+            .into_iter()
+            .flatten()
+            .map(|target| target.account),
+    );
+    let secrets: Vec<&str> = secrets.iter().map(String::as_str).collect();
+
+    match support::write(path, &transcript, &secrets) {
+        Ok(()) => info!("Wrote support bundle to {}", path.display()),
+        Err(e) => error!("Could not write support bundle: {}", e),
+    }
+}
+
+/// Reads commands from stdin and prints the provider's responses, to
+/// speed up adapting to API changes. Deliberately left out of the usage.
+async fn repl() {
+    let mut account = Account::new();
+    let mut input = String::new();
+    loop {
+        print!("> ");
+        input.clear();
+        if io::stdout().flush().is_err()
+            || matches!(io::stdin().read_line(&mut input), Ok(0) | Err(_))
+        {
+            break;
+        }
+
+        let line = input.trim();
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let result = match command {
+            "" => continue,
+            "help" => {
+                println!("{REPL_HELP}");
+                continue;
+            }
+            "quit" | "exit" => break,
+            "login" => repl_login(&mut account, rest).await,
+            "get" | "post" => repl_request(&mut account, command, rest).await,
+            _ => Err(format!("Unknown command {command}, try help").into()),
+        };
+        if let Err(e) = result {
+            error!("{}", e);
+        }
+    }
+}
+
+async fn repl_login(account: &mut Account, rest: &str) -> Result<(), Box<dyn Error>> {
+    let mut fields = rest.split_whitespace();
+    let username = fields.next().ok_or("Usage: login <ACCOUNT> [PASSWORD]")?;
+    let password = match fields.next() {
+        Some(password) => password.to_owned(),
+        None => credentials::load(username)?.ok_or("No saved password, pass one")?,
+    };
+
+    account.login(username, &password).await?;
+    println!("Daily limit {:.2} km", account.daily());
+    Ok(())
+}
+
+async fn repl_request(
+    account: &mut Account,
+    method: &str,
+    rest: &str,
+) -> Result<(), Box<dyn Error>> {
+    let rest = rest.trim();
+    let (path, body) = rest.split_once(' ').unwrap_or((rest, ""));
+    if path.is_empty() {
+        return Err(format!("Usage: {method} <PATH>").into());
+    }
+    let body: Option<Value> = match body.trim() {
+        "" => None,
+        body => Some(serde_json::from_str(body)?),
+    };
+
+    let (status, res) = account.request(method, path, body.as_ref()).await?;
+    println!("{status}");
+    match serde_json::from_str::<Value>(&res) {
+        Ok(json) => println!("{}", serde_json::to_string_pretty(&json)?),
+        Err(_) => println!("{res}"),
+    }
+    Ok(())
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    todo!()
+async fn main() {
+    let vars = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+    let args = Args::parse(
+        container::env_args(vars)
+            .into_iter()
+            .chain(env::args().skip(1)),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("{e}\n\n{USAGE}");
+        process::exit(2);
+    });
+
+    LOGGER.verbose.store(args.verbose, Ordering::Relaxed);
+    LOGGER.json.store(args.log_json, Ordering::Relaxed);
+    if args.support_bundle.is_some() {
+        *LOGGER.transcript.lock().unwrap() = Some(vec![]);
+    }
+    log::set_logger(&LOGGER).expect("logger is only set once");
+    log::set_max_level(if args.verbose || args.support_bundle.is_some() {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+
+    if args.repl {
+        repl().await;
+        return;
+    }
+
+    let ok = if args.selftest {
+        selftest(&args).await
+    } else if let Err(e) = run(&args).await {
+        error!("{}", e);
+        false
+    } else {
+        true
+    };
+
+    if let Some(path) = &args.support_bundle {
+        write_support_bundle(&args, path);
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_mileage() {
+        let args = parse(&["--account", "13800000000", "--mileage", "2.5"]).unwrap();
+        assert_eq!(args.mileage, Some(2.5));
+
+        for mileage in ["inf", "NaN", "0", "-1", "two"] {
+            assert!(parse(&["selftest", "--mileage", mileage]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_window() {
+        let args = parse(&["selftest", "--window", "06:30-07:30"]).unwrap();
+        assert_eq!(
+            args.window,
+            Some((
+                NaiveTime::from_hms_opt(6, 30, 0).unwrap(),
+                NaiveTime::from_hms_opt(7, 30, 0).unwrap(),
+            ))
+        );
+
+        for window in ["07:30-06:30", "06:30", "6:30-25:00"] {
+            assert!(parse(&["selftest", "--window", window]).is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_command_password() {
+        assert_eq!(
+            command_password(r#"printf 'pw-%s\nrest' "$ACCOUNT""#, "13800000000").unwrap(),
+            "pw-13800000000"
+        );
+        assert!(command_password("true", "13800000000").is_err());
+        assert!(command_password("echo pw; exit 1", "13800000000").is_err());
+    }
+
+    #[test]
+    fn test_json_line() {
+        let line = json_line(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("server")
+                .args(format_args!("Could not save the session: \"x\""))
+                .build(),
+        );
+        let line: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["target"], "server");
+        assert_eq!(line["message"], "Could not save the session: \"x\"");
+        assert!(line["timestamp"].is_string());
+    }
 }
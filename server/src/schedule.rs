@@ -0,0 +1,176 @@
+/*
+    Pretty Der6y - A third-party running data upload client.
+    Copyright (C) 2024  Fay Ash
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published
+    by the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU Affero General Public License for more details.
+
+    You should have received a copy of the GNU Affero General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use chrono::{DateTime, Datelike, Duration, Local, Timelike};
+
+use std::str::FromStr;
+
+/// A cron-like schedule of minute, hour, day of month, month and day of
+/// week fields, e.g. `0 18 * * 1,3,5` for 18:00 on Monday, Wednesday and
+/// Friday. Fields take `*`, numbers, ranges like `1-5`, steps like `*/15`
+/// and comma separated lists of those. Sunday is 0 or 7.
+#[derive(Debug)]
+pub struct Schedule {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    // As in cron, a restricted day of month and day of week match either
+    restricted_days: bool,
+}
+
+impl Schedule {
+    /// Returns the first minute after `time` the schedule fires at, or
+    /// `None` if it never fires within a year.
+    pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        (0..366 * 24 * 60)
+            .map(|minute| start + Duration::minutes(minute))
+            .find(|time| self.matches(time))
+    }
+
+    fn matches(&self, time: &DateTime<Local>) -> bool {
+        let day = self.days[time.day() as usize];
+        let weekday = self.weekdays[time.weekday().num_days_from_sunday() as usize];
+        let day = if self.restricted_days {
+            day || weekday
+        } else {
+            day && weekday
+        };
+
+        self.minutes[time.minute() as usize]
+            && self.hours[time.hour() as usize]
+            && self.months[time.month() as usize]
+            && day
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("Expected 5 schedule fields, got {}", fields.len()));
+        };
+
+        let mut weekdays_set = field(weekdays, 0, 7)?;
+        weekdays_set[0] |= weekdays_set[7];
+        weekdays_set.truncate(7);
+
+        Ok(Self {
+            minutes: field(minutes, 0, 59)?,
+            hours: field(hours, 0, 23)?,
+            days: field(days, 1, 31)?,
+            months: field(months, 1, 12)?,
+            weekdays: weekdays_set,
+            restricted_days: days != "*" && weekdays != "*",
+        })
+    }
+}
+
+/// Parses one schedule field into a table indexed by value.
+fn field(text: &str, min: usize, max: usize) -> Result<Vec<bool>, String> {
+    let number = |text: &str| -> Result<usize, String> {
+        match text.parse() {
+            Ok(value) if (min..=max).contains(&value) => Ok(value),
+            _ => Err(format!(
+                "Invalid schedule value {text}, expected {min}-{max}"
+            )),
+        }
+    };
+
+    let mut set = vec![false; max + 1];
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(format!("Invalid schedule step {step}")),
+            },
+            None => (part, None),
+        };
+
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (number(first)?, number(last)?),
+            None if step.is_some() => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if first > last {
+            return Err(format!("Invalid schedule range {range}"));
+        }
+
+        for value in (first..=last).step_by(step.unwrap_or(1)) {
+            set[value] = true;
+        }
+    }
+    Ok(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        // 2024-09-02 is a Monday
+        Local
+            .with_ymd_and_hms(2024, 9, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_weekdays() {
+        let schedule: Schedule = "0 18 * * 1,3,5".parse().unwrap();
+        assert_eq!(schedule.next_after(at(2, 17, 30)), Some(at(2, 18, 0)));
+        assert_eq!(schedule.next_after(at(2, 18, 0)), Some(at(4, 18, 0)));
+        assert_eq!(schedule.next_after(at(6, 19, 0)), Some(at(9, 18, 0)));
+    }
+
+    #[test]
+    fn test_steps_and_ranges() {
+        let schedule: Schedule = "*/20 6-7 * * *".parse().unwrap();
+        assert_eq!(schedule.next_after(at(2, 6, 25)), Some(at(2, 6, 40)));
+        assert_eq!(schedule.next_after(at(2, 7, 40)), Some(at(3, 6, 0)));
+
+        let sunday: Schedule = "30 7 * * 7".parse().unwrap();
+        assert_eq!(sunday.next_after(at(2, 0, 0)), Some(at(8, 7, 30)));
+    }
+
+    #[test]
+    fn test_restricted_days() {
+        // Fires on the 5th and on every Monday
+        let schedule: Schedule = "0 12 5 * 1".parse().unwrap();
+        assert_eq!(schedule.next_after(at(2, 13, 0)), Some(at(5, 12, 0)));
+        assert_eq!(schedule.next_after(at(5, 13, 0)), Some(at(9, 12, 0)));
+    }
+
+    #[test]
+    fn test_invalid() {
+        for schedule in [
+            "0 18 * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+        ] {
+            assert!(schedule.parse::<Schedule>().is_err(), "{schedule}");
+        }
+    }
+}
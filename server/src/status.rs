@@ -0,0 +1,199 @@
+/*
+    Pretty Der6y - A third-party running data upload client.
+    Copyright (C) 2024  Fay Ash
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published
+    by the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU Affero General Public License for more details.
+
+    You should have received a copy of the GNU Affero General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use std::{collections::BTreeMap, error::Error, fmt::Write};
+
+/// What the daemon is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Waiting,
+    Uploading,
+    Retrying,
+    Stopping,
+}
+
+impl State {
+    fn as_str(self) -> &'static str {
+        match self {
+            State::Waiting => "waiting",
+            State::Uploading => "uploading",
+            State::Retrying => "retrying",
+            State::Stopping => "stopping",
+        }
+    }
+}
+
+/// The last upload for one account.
+#[derive(Debug)]
+struct Outcome {
+    at: DateTime<Local>,
+    attempt: u32,
+    error: Option<String>,
+}
+
+/// State of the daemon, served as JSON by `GET /status`.
+#[derive(Debug)]
+pub struct Status {
+    started_at: DateTime<Local>,
+    pub state: State,
+    /// When the next upload starts, including the jitter
+    pub next_upload: Option<DateTime<Local>>,
+    /// The next times the schedule fires
+    pub next_runs: Vec<DateTime<Local>>,
+    /// Failed uploads waiting to be tried again
+    pub queue: usize,
+    results: BTreeMap<String, Outcome>,
+}
+
+impl Status {
+    pub fn new() -> Self {
+        Self {
+            started_at: Local::now(),
+            state: State::Waiting,
+            next_upload: None,
+            next_runs: vec![],
+            queue: 0,
+            results: BTreeMap::new(),
+        }
+    }
+
+    /// Records the result of the `attempt`th upload for `account`.
+    pub fn record(&mut self, account: &str, attempt: u32, error: Option<String>) {
+        let outcome = Outcome {
+            at: Local::now(),
+            attempt,
+            error,
+        };
+        self.results.insert(account.to_owned(), outcome);
+    }
+
+    pub fn to_json(&self) -> Value {
+        let results: serde_json::Map<_, _> = self
+            .results
+            .iter()
+            .map(|(account, outcome)| {
+                let result = json!({
+                    "at": outcome.at.to_rfc3339(),
+                    "attempt": outcome.attempt,
+                    "ok": outcome.error.is_none(),
+                    "error": outcome.error,
+                });
+                (account.clone(), result)
+            })
+            .collect();
+
+        json!({
+            "state": self.state.as_str(),
+            "started_at": self.started_at.to_rfc3339(),
+            "next_upload": self.next_upload.map(|time| time.to_rfc3339()),
+            "next_runs": self.next_runs.iter().map(DateTime::to_rfc3339).collect::<Vec<_>>(),
+            "queue": self.queue,
+            "results": results,
+        })
+    }
+}
+
+/// Asks the daemon answering on `addr` for its status.
+pub async fn fetch(addr: &str) -> Result<Value, Box<dyn Error>> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream
+        .write_all(b"GET /status HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or("Invalid status response")?;
+    if !head.starts_with("HTTP/1.1 200") {
+        return Err(format!(
+            "Status request failed: {}",
+            head.lines().next().unwrap_or(head)
+        )
+        .into());
+    }
+    Ok(serde_json::from_str(body)?)
+}
+
+/// Formats a status fetched with [`fetch`] for people to read.
+pub fn describe(status: &Value) -> String {
+    let time = |time: &Value| {
+        time.as_str()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "-".to_owned())
+    };
+
+    let mut text = String::new();
+    let _ = writeln!(text, "State: {}", status["state"].as_str().unwrap_or("-"));
+    let _ = writeln!(text, "Started at: {}", time(&status["started_at"]));
+    let _ = writeln!(text, "Next upload at: {}", time(&status["next_upload"]));
+    if let Some(runs) = status["next_runs"].as_array() {
+        let runs: Vec<_> = runs.iter().map(time).collect();
+        let _ = writeln!(text, "Scheduled at: {}", runs.join(", "));
+    }
+    let _ = writeln!(text, "Queued retries: {}", status["queue"]);
+    if let Some(results) = status["results"].as_object() {
+        for (account, result) in results {
+            let outcome = match result["error"].as_str() {
+                Some(error) => format!("failed ({error})"),
+                None => "succeeded".to_owned(),
+            };
+            let _ = writeln!(
+                text,
+                "{account}: {outcome} at {}, attempt {}",
+                time(&result["at"]),
+                result["attempt"]
+            );
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_json() {
+        let mut status = Status::new();
+        status.state = State::Retrying;
+        status.queue = 1;
+        status.record("13800000000", 1, None);
+        status.record("13800000001", 2, Some("Token expired".to_owned()));
+
+        let json = status.to_json();
+        assert_eq!(json["state"], "retrying");
+        assert_eq!(json["queue"], 1);
+        assert!(json["next_upload"].is_null());
+        assert_eq!(json["results"]["13800000000"]["ok"], true);
+        assert_eq!(json["results"]["13800000001"]["attempt"], 2);
+        assert_eq!(json["results"]["13800000001"]["error"], "Token expired");
+
+        let text = describe(&json);
+        assert!(text.starts_with("State: retrying\n"));
+        assert!(text.contains("13800000001: failed (Token expired) at "));
+    }
+}
@@ -0,0 +1,126 @@
+/*
+    Pretty Der6y - A third-party running data upload client.
+    Copyright (C) 2024  Fay Ash
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published
+    by the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU Affero General Public License for more details.
+
+    You should have received a copy of the GNU Affero General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use chrono::Local;
+
+use std::{
+    env,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// JSON keys whose string values are never written to a support bundle.
+const SECRET_KEYS: &[&str] = &[
+    "accessToken",
+    "password",
+    "pyd",
+    "signDigital",
+    "token",
+    "userName",
+];
+const REDACTED: &str = "[redacted]";
+
+/// Replaces every occurrence of `secrets` in `line`, and the string values
+/// of [`SECRET_KEYS`] in the request and response bodies it may contain.
+pub fn redact(line: &str, secrets: &[&str]) -> String {
+    let mut line = line.to_owned();
+    for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+        line = line.replace(secret, REDACTED);
+    }
+
+    for key in SECRET_KEYS {
+        let key = format!("\"{key}\"");
+        let mut from = 0;
+        while let Some(found) = line[from..].find(&key) {
+            from += found + key.len();
+            let Some(value) = line[from..]
+                .trim_start()
+                .strip_prefix(':')
+                .map(str::trim_start)
+                .and_then(|rest| rest.strip_prefix('"'))
+            else {
+                continue;
+            };
+
+            let start = line.len() - value.len();
+            let mut escaped = false;
+            let Some(len) = value.find(|c| {
+                let end = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                end
+            }) else {
+                break;
+            };
+            line.replace_range(start..start + len, REDACTED);
+            from = start + REDACTED.len();
+        }
+    }
+    line
+}
+
+/// Writes `transcript` to `path` after a short header, redacting every
+/// line with [`redact`].
+pub fn write(path: &Path, transcript: &[String], secrets: &[&str]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "server {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(file, "{} {}", env::consts::OS, env::consts::ARCH)?;
+    writeln!(file, "Written at {}", Local::now().to_rfc3339())?;
+    writeln!(file)?;
+    for line in transcript {
+        writeln!(file, "{}", redact(line, secrets))?;
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_secrets() {
+        assert_eq!(
+            redact(
+                "Get token for 13800000000 with hunter2",
+                &["13800000000", "hunter2", ""]
+            ),
+            "Get token for [redacted] with [redacted]"
+        );
+    }
+
+    #[test]
+    fn test_redact_json() {
+        let login = "Login json: {\n  \"entrance\" : \"1\",\n  \"password\" : \"a\\\"b\",\n  \"userName\" : \"x\"\n}";
+        assert_eq!(
+            redact(login, &[]),
+            "Login json: {\n  \"entrance\" : \"1\",\n  \"password\" : \"[redacted]\",\n  \"userName\" : \"[redacted]\"\n}"
+        );
+        assert_eq!(
+            redact(
+                r#"{"token":"abc","tokenType":"x","id":1,"token":null}"#,
+                &[]
+            ),
+            r#"{"token":"[redacted]","tokenType":"x","id":1,"token":null}"#
+        );
+        // The envelope's key is public, so the token could be decrypted
+        assert_eq!(
+            redact(r#"{"code":0,"data":{"t":1,"pyd":"ns7Q24+/="}}"#, &[]),
+            r#"{"code":0,"data":{"t":1,"pyd":"[redacted]"}}"#
+        );
+    }
+}
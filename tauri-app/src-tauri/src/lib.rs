@@ -16,22 +16,44 @@
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Duration, Local};
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
-use lib::Account;
-use tauri::{async_runtime::Mutex, Manager, State};
+use lib::{credentials, route, Account, UploadStage};
+use tauri::{async_runtime::Mutex, AppHandle, Emitter, Manager, State};
 
 #[tauri::command]
 async fn login(
     state: State<'_, Mutex<Account>>,
     username: &str,
     password: &str,
-) -> Result<(), String> {
+    remember: bool,
+) -> Result<Option<String>, String> {
     let mut account = state.lock().await;
     account
         .login(username, password)
         .await
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?;
+
+    // Logged in either way, so a missing keyring only costs the saved password
+    let saved = if remember {
+        credentials::save(username, password)
+    } else {
+        credentials::forget(username)
+    };
+    Ok(saved
+        .err()
+        .map(|e| format!("Could not update the saved password: {}", e)))
+}
+
+#[tauri::command]
+fn load_password(username: &str) -> Result<Option<String>, String> {
+    credentials::load(username).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_proxy(state: State<'_, Mutex<Account>>, proxy: Option<&str>) -> Result<(), String> {
+    let mut account = state.lock().await;
+    account.set_proxy(proxy).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -41,21 +63,101 @@ async fn get_daily_limit(state: State<'_, Mutex<Account>>) -> Result<f64, String
 }
 
 #[tauri::command]
-async fn upload(
+fn load_route(name: &str, content: &str) -> Result<String, String> {
+    route::load(name, content).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_track(
     state: State<'_, Mutex<Account>>,
     geojson: &str,
     mileage: f64,
-    end_time: i64,
-) -> Result<(), String> {
+    jitter: f64,
+    spacing: Option<f64>,
+) -> Result<String, String> {
+    let geojson = route::normalize(geojson).map_err(|e| e.to_string())?;
     let mut account = state.lock().await;
+    account.set_jitter(jitter).map_err(|e| e.to_string())?;
+    account.set_spacing(spacing).map_err(|e| e.to_string())?;
+    account
+        .export_track(&geojson, mileage)
+        .map_err(|e| e.to_string())
+}
+
+/// Aborts the upload in flight, if any. Emptied once the run is being sent,
+/// as it may be recorded even if the request is dropped.
+#[derive(Default)]
+struct Cancel(std::sync::Mutex<Option<Box<dyn FnOnce() + Send>>>);
+
+#[tauri::command]
+async fn upload(
+    app: AppHandle,
+    cancel: State<'_, Cancel>,
+    geojson: &str,
+    mileage: f64,
+    end_time: i64,
+    duration: Option<i64>,
+    humanize: bool,
+    jitter: f64,
+    spacing: Option<f64>,
+    dry_run: bool,
+) -> Result<Option<String>, String> {
+    let geojson = route::normalize(geojson).map_err(|e| e.to_string())?;
     let end_time: DateTime<Local> = DateTime::from_timestamp_millis(end_time)
         .ok_or("Invalid timestamp")?
         .with_timezone(&Local);
+    let duration = duration
+        .map(|seconds| Duration::try_seconds(seconds).ok_or("Invalid duration"))
+        .transpose()?;
 
-    account
-        .upload_running(geojson, mileage, end_time)
-        .await
-        .map_err(|e| e.to_string())
+    let task = {
+        // Held until the abort handle is stored, so the task can't take it first
+        let mut abort = cancel.0.lock().unwrap();
+        let task = tauri::async_runtime::spawn(async move {
+            let state = app.state::<Mutex<Account>>();
+            let mut account = state.lock().await;
+            let progress = app.clone();
+            account.set_humanize(humanize);
+            account.set_jitter(jitter).map_err(|e| e.to_string())?;
+            account.set_spacing(spacing).map_err(|e| e.to_string())?;
+            account.set_progress(move |stage| {
+                if stage == UploadStage::Uploading {
+                    progress.state::<Cancel>().0.lock().unwrap().take();
+                }
+                let _ = progress.emit("upload-progress", stage);
+            });
+            if dry_run {
+                return account
+                    .preview_running(&geojson, mileage, end_time, duration)
+                    .map(|summary| Some(summary.to_string()))
+                    .map_err(|e| e.to_string());
+            }
+
+            account
+                .upload_running(&geojson, mileage, end_time, duration)
+                .await
+                .map(|()| None)
+                .map_err(|e| e.to_string())
+        });
+        let handle = task.inner().abort_handle();
+        *abort = Some(Box::new(move || handle.abort()));
+        task
+    };
+
+    let result = task.await;
+    cancel.0.lock().unwrap().take();
+    match result {
+        Ok(result) => result,
+        Err(tauri::Error::JoinError(e)) if e.is_cancelled() => Err("Upload cancelled".to_string()),
+        Err(e) => Err(format!("Upload failed: {}", e)),
+    }
+}
+
+#[tauri::command]
+fn cancel_upload(cancel: State<'_, Cancel>) {
+    if let Some(abort) = cancel.0.lock().unwrap().take() {
+        abort();
+    }
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -68,10 +170,20 @@ pub fn run() {
 
             let account = Account::new();
             app.manage(Mutex::new(account));
+            app.manage(Cancel::default());
 
             Ok(())
         })
-        .invoke_handler(tauri::generate_handler![login, get_daily_limit, upload])
+        .invoke_handler(tauri::generate_handler![
+            login,
+            load_password,
+            set_proxy,
+            get_daily_limit,
+            load_route,
+            export_track,
+            upload,
+            cancel_upload
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -16,26 +16,47 @@
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-import { createSignal } from "solid-js";
+import { createSignal, onMount } from "solid-js";
 import { Background } from "./components/Skeleton";
 import Button from "./components/Button";
 import Input from "./components/Input";
 import Icon from "./components/Icon";
 import { useLogined } from "./App";
 import { invoke } from "@tauri-apps/api/core";
-import { faLock, faUser } from "@fortawesome/free-solid-svg-icons";
+import { faGlobe, faLock, faUser } from "@fortawesome/free-solid-svg-icons";
 import { useLogger } from "./components/Logger";
 import TwoColumn from "./layouts/TwoColumn";
 
 const image = "https://s2.loli.net/2024/08/26/gZOYyS7aECHuF9z.webp";
 
 export default function Login() {
-  const [username, setUsername] = createSignal("");
+  const [username, setUsername] = createSignal(
+    localStorage.getItem("username") ?? "",
+  );
   const [password, setPassword] = createSignal("");
+  const [remember, setRemember] = createSignal(false);
+  const [proxy, setProxy] = createSignal(localStorage.getItem("proxy") ?? "");
   const [, setLogined] = useLogined();
   const [pending, setPending] = createSignal(false);
   const logger = useLogger();
 
+  onMount(() => {
+    if (!username()) {
+      return;
+    }
+
+    invoke<string | null>("load_password", { username: username() })
+      .then((saved) => {
+        if (saved !== null) {
+          setPassword(saved);
+          setRemember(true);
+        }
+      })
+      .catch((error) => {
+        logger?.error(`Error loading saved password: ${error}`);
+      });
+  });
+
   return (
     <TwoColumn
       first={
@@ -53,8 +74,22 @@ export default function Login() {
             onSubmit={(event) => {
               event.preventDefault();
               setPending(true);
-              invoke("login", { username: username(), password: password() })
-                .then(() => setLogined(true))
+              localStorage.setItem("username", username());
+              localStorage.setItem("proxy", proxy());
+              invoke("set_proxy", { proxy: proxy() || null })
+                .then(() =>
+                  invoke<string | null>("login", {
+                    username: username(),
+                    password: password(),
+                    remember: remember(),
+                  }),
+                )
+                .then((warning) => {
+                  if (warning !== null) {
+                    logger?.warn(warning);
+                  }
+                  setLogined(true);
+                })
                 .catch((error) => {
                   logger?.error(`Error logging in: ${error}`);
                 })
@@ -94,6 +129,22 @@ export default function Login() {
               }}
               prefixContent={<Icon icon={faLock} classes="text-gray-400" />}
             />
+            <Input
+              name="proxy"
+              value={proxy()}
+              type="url"
+              placeholder="Proxy (optional)"
+              onInput={(event) => setProxy(event.target.value)}
+              prefixContent={<Icon icon={faGlobe} classes="text-gray-400" />}
+            />
+            <label class="flex items-center gap-2">
+              <input
+                type="checkbox"
+                checked={remember()}
+                onChange={(event) => setRemember(event.currentTarget.checked)}
+              />
+              <span class="text-gray-500 font-bold">Remember password</span>
+            </label>
             <Button type="submit" disabled={pending()}>
               Login
             </Button>
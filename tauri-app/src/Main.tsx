@@ -24,23 +24,49 @@ import TimePicker from "./components/TimePicker";
 import Slider from "./components/Slider";
 import Uploader from "./components/Uploader";
 import Button from "./components/Button";
+import Input from "./components/Input";
 import { useLogger } from "./components/Logger";
 import * as L from "leaflet";
 import { invoke } from "@tauri-apps/api/core";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
 import isDef from "./helpers/isDef";
+import reducedMotion, { setReducedMotion } from "./helpers/reducedMotion";
+
+const stages: Record<string, string> = {
+  generating: "Generating...",
+  uploading: "Uploading...",
+  verifying: "Verifying...",
+};
+
+// Past these stages the run may already be recorded, so it can't be cancelled
+const sent = ["uploading", "verifying"];
 
 export default function Main() {
   const logger = useLogger();
   const [time, setTime] = createSignal(new Date());
   const [percentage, setPercentage] = createSignal(0);
+  const [duration, setDuration] = createSignal("");
+  const [humanize, setHumanize] = createSignal(
+    localStorage.getItem("humanize") !== "false",
+  );
+  const [noise, setNoise] = createSignal(
+    localStorage.getItem("noise") ?? "0.5",
+  );
+  const [spacing, setSpacing] = createSignal(
+    localStorage.getItem("spacing") ?? "",
+  );
+  const [dryRun, setDryRun] = createSignal(false);
   const [file, setFile] = createSignal<File>();
+  const [route, setRoute] = createSignal<string>();
   const [map, setMap] = createSignal<L.Map>();
   const [daily, setDaily] = createSignal(0);
   const [pending, setPending] = createSignal(false);
+  const [stage, setStage] = createSignal<string>();
 
   const mileage = createMemo(() => (percentage() * daily()) / 100);
 
   let tick: NodeJS.Timeout;
+  let unlisten: UnlistenFn | undefined;
 
   onMount(() => {
     (function updateTime() {
@@ -52,6 +78,12 @@ export default function Main() {
       tick = setTimeout(updateTime, delay);
     })();
 
+    listen<string>("upload-progress", (event) => setStage(event.payload)).then(
+      (fn) => {
+        unlisten = fn;
+      },
+    );
+
     invoke("get_daily_limit")
       .then(setDaily)
       .catch((error) => {
@@ -62,6 +94,7 @@ export default function Main() {
 
   onCleanup(() => {
     clearTimeout(tick);
+    unlisten?.();
   });
 
   function passedSetTime(time: Date | ((prev: Date) => Date)) {
@@ -76,20 +109,43 @@ export default function Main() {
       setFile();
     }
 
+    setRoute();
+
     if (isDef(file)) {
+      const selected = file();
       const reader = new FileReader();
       reader.onload = (event) => {
         const data = event.target?.result;
         if (typeof data === "string") {
-          const json = JSON.parse(data);
-          const geojson = L.geoJSON(json);
-          if (isDef(map)) {
-            geojson.addTo(map());
-            map().fitBounds(geojson.getBounds());
-          }
+          invoke<string>("load_route", {
+            name: selected.name,
+            content: data,
+          })
+            .then((route) => {
+              // A newer file may have been picked while this one was loading
+              if (file() !== selected) {
+                return;
+              }
+
+              setRoute(route);
+              const geojson = L.geoJSON(JSON.parse(route));
+              if (isDef(map)) {
+                geojson.addTo(map());
+                map().fitBounds(geojson.getBounds(), {
+                  animate: !reducedMotion(),
+                });
+              }
+            })
+            .catch((error) => {
+              if (file() !== selected) {
+                return;
+              }
+
+              logger?.error(`Error loading route: ${error}`);
+            });
         }
       };
-      reader.readAsText(file());
+      reader.readAsText(selected);
     }
 
     return undefined;
@@ -108,37 +164,38 @@ export default function Main() {
             class="space-y-4"
             onSubmit={(event) => {
               event.preventDefault();
-              if (!file()) {
-                logger?.warn("No file selected!");
+              if (!isDef(route)) {
+                logger?.warn(
+                  file() ? "Route is not loaded!" : "No file selected!",
+                );
                 return;
               }
               setPending(true);
+              setStage();
 
-              const reader = new FileReader();
-              reader.onload = (event) => {
-                const data = event.target?.result;
-                if (typeof data === "string") {
-                  invoke("upload", {
-                    geojson: data,
-                    mileage: mileage(),
-                    endTime: time().getTime(),
-                  })
-                    .then(() => {
-                      logger?.info("Upload successful!");
-                    })
-                    .catch((error) => {
-                      logger?.error(`Error uploading: ${error}`);
-                    })
-                    .finally(() => setPending(false));
-                } else {
-                  logger?.error("Invalid file type!");
-                  setPending(false);
-                }
-              };
-
-              if (isDef(file)) {
-                reader.readAsText(file());
-              }
+              invoke<string | null>("upload", {
+                geojson: route(),
+                mileage: mileage(),
+                endTime: time().getTime(),
+                duration: duration()
+                  ? Math.round(Number(duration()) * 60)
+                  : null,
+                humanize: humanize(),
+                jitter: Number(noise()),
+                spacing: spacing() ? Number(spacing()) : null,
+                dryRun: dryRun(),
+              })
+                .then((summary) => {
+                  logger?.info(
+                    summary === null
+                      ? "Upload successful!"
+                      : `Dry run, not uploaded: ${summary}`,
+                  );
+                })
+                .catch((error) => {
+                  logger?.error(`Error uploading: ${error}`);
+                })
+                .finally(() => setPending(false));
             }}
           >
             <label class="block">
@@ -158,13 +215,127 @@ export default function Main() {
               </div>
               <Slider value={[percentage, setPercentage]} />
             </label>
+            <label class="block">
+              <span class="text-gray-500 font-bold">Duration</span>
+              <Input
+                type="number"
+                min="1"
+                step="any"
+                value={duration()}
+                placeholder="Auto"
+                onInput={(event) => setDuration(event.target.value)}
+                suffixContent={<span class="text-gray-400 text-sm">min</span>}
+              />
+            </label>
+            <label class="block">
+              <span class="text-gray-500 font-bold">Point noise</span>
+              <Input
+                type="number"
+                min="0"
+                step="any"
+                value={noise()}
+                placeholder="Off"
+                onInput={(event) => {
+                  setNoise(event.target.value);
+                  localStorage.setItem("noise", event.target.value);
+                }}
+                suffixContent={<span class="text-gray-400 text-sm">m</span>}
+              />
+            </label>
+            <label class="block">
+              <span class="text-gray-500 font-bold">Point spacing</span>
+              <Input
+                type="number"
+                min="1"
+                step="any"
+                value={spacing()}
+                placeholder="Route points"
+                onInput={(event) => {
+                  setSpacing(event.target.value);
+                  localStorage.setItem("spacing", event.target.value);
+                }}
+                suffixContent={<span class="text-gray-400 text-sm">m</span>}
+              />
+            </label>
             <Uploader
               file={[file, updateFile]}
-              accept=".geojson,application/geo+json"
+              accept={[
+                ".geojson,application/geo+json",
+                ".gpx,application/gpx+xml",
+                ".kml,application/vnd.google-earth.kml+xml",
+                ".tcx,application/vnd.garmin.tcx+xml",
+              ].join(",")}
             />
-            <Button type="submit" disabled={pending()}>
-              Upload
-            </Button>
+            <label class="flex items-center gap-2">
+              <input
+                type="checkbox"
+                checked={reducedMotion()}
+                onChange={(event) =>
+                  setReducedMotion(event.currentTarget.checked)
+                }
+              />
+              <span class="text-gray-500 font-bold">Reduce motion</span>
+            </label>
+            <label class="flex items-center gap-2">
+              <input
+                type="checkbox"
+                checked={humanize()}
+                onChange={(event) => {
+                  setHumanize(event.currentTarget.checked);
+                  localStorage.setItem(
+                    "humanize",
+                    String(event.currentTarget.checked),
+                  );
+                }}
+              />
+              <span class="text-gray-500 font-bold">Humanize</span>
+            </label>
+            <label class="flex items-center gap-2">
+              <input
+                type="checkbox"
+                checked={dryRun()}
+                onChange={(event) => setDryRun(event.currentTarget.checked)}
+              />
+              <span class="text-gray-500 font-bold">Dry run</span>
+            </label>
+            <div class="flex gap-4">
+              <Button
+                disabled={pending() || !isDef(route)}
+                onClick={() => {
+                  invoke<string>("export_track", {
+                    geojson: route(),
+                    mileage: mileage(),
+                    jitter: Number(noise()),
+                    spacing: spacing() ? Number(spacing()) : null,
+                  })
+                    .then((track) => navigator.clipboard.writeText(track))
+                    .then(() => {
+                      logger?.info("Track copied to clipboard as GeoJSON!");
+                    })
+                    .catch((error) => {
+                      logger?.error(`Error exporting track: ${error}`);
+                    });
+                }}
+              >
+                Export
+              </Button>
+              <Button type="submit" disabled={pending()}>
+                {pending()
+                  ? (stages[stage() ?? ""] ?? "Uploading...")
+                  : "Upload"}
+              </Button>
+              {pending() && !sent.includes(stage() ?? "") && (
+                <Button
+                  onClick={() => {
+                    invoke("cancel_upload").catch((error) => {
+                      logger?.error(`Error cancelling upload: ${error}`);
+                    });
+                  }}
+                >
+                  Cancel
+                </Button>
+              )}
+            </div>
           </form>
         </div>
       }
@@ -61,7 +61,7 @@ export const Button = (
       <For each={ripples()}>
         {({ x, y, size }) => (
           <span
-            class="animate-ripple absolute rounded-full bg-white/50 pointer-events-none"
+            class="animate-ripple reduced:hidden absolute rounded-full bg-white/50 pointer-events-none"
             style={{
               top: `${y}px`,
               left: `${x}px`,
@@ -17,9 +17,16 @@
 */
 
 import * as L from "leaflet";
-import { type JSX, onMount, type Signal, splitProps } from "solid-js";
+import {
+  createEffect,
+  type JSX,
+  onMount,
+  type Signal,
+  splitProps,
+} from "solid-js";
 import "leaflet/dist/leaflet.css";
 import { useLogger } from "./Logger";
+import reducedMotion from "../helpers/reducedMotion";
 
 export const LeafletMap = (
   props: JSX.HTMLAttributes<HTMLDivElement> & {
@@ -35,6 +42,10 @@ export const LeafletMap = (
   onMount(() => {
     const map = L.map(ref);
     setMap(map);
+    createEffect(() => {
+      // Read on every drag end, unlike the animation options
+      map.options.inertia = !reducedMotion();
+    });
     navigator.geolocation.getCurrentPosition(
       (position) => {
         map.setView([position.coords.latitude, position.coords.longitude], 13);
@@ -89,7 +89,7 @@ const Log = (props: {
 
   return (
     <li
-      class="flex transition-opacity mt-4 justify-between items-center rounded-lg text-sm text-white px-4 py-2 shadow-lg"
+      class="flex transition-opacity reduced:transition-none mt-4 justify-between items-center rounded-lg text-sm text-white px-4 py-2 shadow-lg"
       classList={{
         [levelColors[level]]: true,
         "opacity-100": isVisible(),
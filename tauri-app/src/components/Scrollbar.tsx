@@ -102,7 +102,7 @@ const Scrollbar: ParentComponent<JSX.HTMLAttributes<HTMLDivElement>> = (
         {local.children}
         <div
           style={{ height: height(), top: top() }}
-          class="absolute right-0 w-2 bg-gray-400/50 hover:bg-gray-600/50 transition-opacity duration-300 rounded"
+          class="absolute right-0 w-2 bg-gray-400/50 hover:bg-gray-600/50 transition-opacity duration-300 reduced:transition-none rounded"
           classList={{
             "opacity-100": show() || dragging(),
             "opacity-0": !show() && !dragging(),
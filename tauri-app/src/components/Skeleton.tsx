@@ -23,7 +23,7 @@ export const Skeleton = (props: { height?: string }) => {
 
   return (
     <div
-      class="animate-pulse bg-gray-300 w-full"
+      class="animate-pulse reduced:animate-none bg-gray-300 w-full"
       style={{
         height: merged.height,
       }}
@@ -51,7 +51,7 @@ export const Background = (props: { src: string; children: JSX.Element }) => {
   return (
     <Show when={loaded()} fallback={<Skeleton height="100%" />}>
       <div
-        class="w-full h-full bg-cover bg-center transition-opacity duration-500"
+        class="w-full h-full bg-cover bg-center transition-opacity duration-500 reduced:transition-none"
         classList={{
           "opacity-100": loaded(),
           "opacity-0": !loaded(),
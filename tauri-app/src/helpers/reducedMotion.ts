@@ -0,0 +1,36 @@
+import { createEffect, createRoot, createSignal } from "solid-js";
+
+const STORAGE_KEY = "reducedMotion";
+
+// Follows the OS "reduce motion" accessibility setting unless the user has
+// picked one in the app. The `reduced-motion` class on the root element
+// drives the `reduced:` variant in uno.config.ts.
+const [reducedMotion, setReducedMotion] = createRoot(() => {
+  const query = matchMedia("(prefers-reduced-motion: reduce)");
+  const [system, setSystem] = createSignal(query.matches);
+  query.addEventListener("change", (event) => setSystem(event.matches));
+
+  const stored = localStorage.getItem(STORAGE_KEY);
+  const [override, setOverride] = createSignal<boolean | undefined>(
+    stored === null ? undefined : stored === "true",
+  );
+
+  const reducedMotion = () => override() ?? system();
+
+  createEffect(() => {
+    document.documentElement.classList.toggle(
+      "reduced-motion",
+      reducedMotion(),
+    );
+  });
+
+  function setReducedMotion(value: boolean) {
+    localStorage.setItem(STORAGE_KEY, String(value));
+    setOverride(value);
+  }
+
+  return [reducedMotion, setReducedMotion] as const;
+});
+
+export { setReducedMotion };
+export default reducedMotion;
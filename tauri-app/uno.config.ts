@@ -21,6 +21,26 @@ import { defineConfig, presetAttributify, presetUno } from "unocss";
 
 export default defineConfig({
   presets: [presetUno(), presetAttributify()],
+  variants: [
+    // `reduced:` applies while reduced motion is on, see helpers/reducedMotion.ts
+    (matcher) => {
+      if (!matcher.startsWith("reduced:")) return matcher;
+      return {
+        matcher: matcher.slice("reduced:".length),
+        selector: (selector) => `.reduced-motion ${selector}`,
+      };
+    },
+  ],
+  preflights: [
+    {
+      // Leaflet animates zooms with CSS transitions and fades tiles in from
+      // script, neither of which can be switched off after the map is created.
+      getCSS: () => `
+        .reduced-motion .leaflet-container * { transition: none !important; }
+        .reduced-motion .leaflet-tile { opacity: 1 !important; }
+      `,
+    },
+  ],
   theme: {
     animation: {
       keyframes: {